@@ -1,8 +1,13 @@
 #![allow(unused_parens)]
 extern crate sketches;
 
-use sketches::midi;
+use sketches::midi::{
+    self, Event, HighResDecoder, HighResEvent, Learn, MidiConfig, MidiEvent, MidiSource, Param, Recorder,
+};
 use sketches::midi::twister::constants as twister_constants;
+use sketches::midi::output::to_u7;
+use sketches::midi::twister::{self, lights, Encoder, Simulator, TwisterState, VirtualTwister};
+use sketches::util;
 
 use midir::MidiInputConnection;
 use nannou::image;
@@ -17,7 +22,7 @@ use std::{
     time::Duration,
     u8,
 };
-use wmidi::MidiMessage;
+use wmidi::U7;
 
 
 fn main() {
@@ -30,6 +35,16 @@ struct Model {
     texture: wgpu::Texture,
     show_frame_count: bool,
     recording: bool,
+    midi_recorder: Recorder,
+    learn: Learn,
+    params: Params,
+    last_update: Duration,
+    _connection: Option<MidiInputConnection<()>>,
+    simulator: VirtualTwister,
+    receiver: Receiver<Event>,
+}
+
+struct Params {
     speed: f32,
     opacity: f32,
     crunchiness: f32,
@@ -37,9 +52,20 @@ struct Model {
     red_w: f32,
     green_w: f32,
     blue_w: f32,
-    last_update: Duration,
-    _connection: Option<MidiInputConnection<()>>,
-    receiver: Receiver<Vec<u8>>,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            speed: 1.0,
+            opacity: 1.0,
+            crunchiness: 1.0,
+
+            red_w: 1.0,
+            green_w: 1.0,
+            blue_w: 1.0,
+        }
+    }
 }
 
 fn model(app: &App) -> Model {
@@ -53,37 +79,87 @@ fn model(app: &App) -> Model {
         .size(image.width(), image.height()) // set window size to image dimensions
         .view(view)
         .key_pressed(key_pressed)
+        .key_released(key_released)
         .build()
         .unwrap();
 
     let (tx, rx) = channel();
 
-    Model {
+    let mut learn = midi_learn();
+    let bindings = "./bindings/".to_owned() + &app.exe_name().unwrap() + ".toml";
+    if let Err(e) = learn.load(bindings) {
+        eprintln!("{}", e);
+    }
+
+    let model = Model {
         frame: image.clone(),
         texture: wgpu::Texture::from_image(app, &image),
         image,
 
-        speed: 1.0,
-        opacity: 1.0,
-        crunchiness: 1.0,
+        params: Params::default(),
         last_update: Duration::new(0, 0),
 
-        red_w: 1.0,
-        green_w: 1.0,
-        blue_w: 1.0,
-
-        _connection: midi::init(tx),
+        simulator: simulator().start(tx.clone()).unwrap(),
+        _connection: MidiConfig::default()
+            .high_res(HighResDecoder::new())
+            .start(tx)
+            .map_err(|e| eprintln!("{}", e))
+            .ok(),
         receiver: rx,
         show_frame_count: false,
         recording: false,
+        midi_recorder: Recorder::new(),
+        learn,
+    };
+    sync_controller(&model);
+    model
+}
+
+// Where each encoder sits for the given params, so the rings and the
+// simulator start from the sketch's values.
+fn encoder_values(params: &Params) -> [(Encoder, U7); 6] {
+    let speed = sketches::util::inverse_speed_func(params.speed).round() as u8;
+    [
+        (Encoder::new(0, 0, 0), U7::from_u8_lossy(speed)),
+        (Encoder::new(0, 0, 1), to_u7(params.opacity)),
+        (Encoder::new(0, 0, 2), to_u7(params.crunchiness)),
+        (Encoder::new(0, 1, 0), to_u7(params.red_w)),
+        (Encoder::new(0, 1, 1), to_u7(params.green_w)),
+        (Encoder::new(0, 1, 2), to_u7(params.blue_w)),
+    ]
+}
+
+// Match the rings to the starting values and colour the weight knobs after
+// the channel they drive.
+fn sync_controller(model: &Model) {
+    let synced = MidiConfig::default().connect_output().and_then(|mut output| {
+        for (encoder, value) in encoder_values(&model.params) {
+            let (channel, control) = encoder.to_cc();
+            output.control_change(channel, control, value)?;
+        }
+        twister::set_color(&mut output, Encoder::new(0, 1, 0), lights::RED)?;
+        twister::set_color(&mut output, Encoder::new(0, 1, 1), lights::GREEN)?;
+        twister::set_color(&mut output, Encoder::new(0, 1, 2), lights::BLUE)
+    });
+    if let Err(e) = synced {
+        eprintln!("{}", e);
+    }
+}
+
+// Keyboard stand-in for the Twister, starting from the default params.
+fn simulator() -> Simulator {
+    let mut state = TwisterState::new();
+    for (encoder, value) in encoder_values(&Params::default()) {
+        state.set(encoder, value);
     }
+    Simulator::new().state(&state)
 }
 
 fn update(app: &App, model: &mut Model, update: Update) {
     update_params(model);
 
     if (update.since_start - model.last_update
-        > Duration::from_millis((250.0 / model.speed).trunc() as u64))
+        > Duration::from_millis((250.0 / model.params.speed).trunc() as u64))
     {
         model.last_update = update.since_start;
         update_image(model);
@@ -92,45 +168,72 @@ fn update(app: &App, model: &mut Model, update: Update) {
     }
 }
 
+// The recording gets every message from the controller, the params only the
+// last value of each control this frame. Simulator timestamps start at 0
+// rather than the backend's origin, so they're left out of the recording.
 fn update_params(model: &mut Model) {
-    for data in model.receiver.try_iter() {
-        match MidiMessage::try_from(data.as_slice()) {
-            Err(e) => {
-                eprint!("Invalid midi message {:?}\n", e);
+    let events: Vec<Event> = model.receiver.try_iter().collect();
+    for event in &events {
+        match event {
+            Event::Midi(event) if &*event.port != model.simulator.port() => {
+                model.midi_recorder.record(event)
             }
-            Ok(midi_message) => match midi_message {
-                wmidi::MidiMessage::ControlChange(channel, note, velocity) => {
-                    match (channel, note) {
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_ZERO) => {
-                            let v: u8 = velocity.into();
-                            model.speed = sketches::util::speed_func(v as f32)
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_ONE) => {
-                            let v: u8 = velocity.into();
-                            model.opacity = (v as f32) / 127.0;
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_TWO) => {
-                            let v: u8 = velocity.into();
-                            model.crunchiness = (v as f32) / 127.0;
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ONE_ZERO) => {
-                            let v: u8 = velocity.into();
-                            model.red_w = (v as f32) / 127.0;
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ONE_ONE) => {
-                            let v: u8 = velocity.into();
-                            model.green_w = (v as f32) / 127.0; 
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ONE_TWO) => {
-                            let v: u8 = velocity.into();
-                            model.blue_w = (v as f32) / 127.0; 
-                        }
-                        _ => {}
-                    }
-                }
-                _ => {}
-            },
-        };
+            _ => {}
+        }
+    }
+    for event in midi::coalesce(events, twister::is_turn) {
+        match event {
+            Event::Midi(event) if !is_on(&event, SPEED_CHANNEL) => {
+                apply_midi(&mut model.params, &mut model.learn, &event)
+            }
+            Event::HighRes(event) => apply_high_res(&mut model.params, &event),
+            _ => {}
+        }
+    }
+}
+
+// Speed steps visibly on a 7-bit knob. A 14-bit controller can drive it
+// smoothly through NRPN 0 instead, over the same range as encoder 0/0. NRPN
+// is sent as CC 99/98 and data entry CC 6/38, and CC 6 on channel 1 is the
+// Twister's encoder 1/2, so it goes on a channel the Twister doesn't use.
+// The CCs themselves are kept away from the 7-bit params and learning.
+const SPEED_CHANNEL: wmidi::Channel = wmidi::Channel::Ch16;
+const SPEED_NRPN: Param = Param::Nrpn(0);
+
+fn is_on(event: &MidiEvent, channel: wmidi::Channel) -> bool {
+    event.message.channel() == Some(channel)
+}
+
+fn apply_high_res(params: &mut Params, event: &HighResEvent) {
+    if event.channel == SPEED_CHANNEL && event.param == SPEED_NRPN {
+        params.speed = util::speed_func(util::unipolar14(event.value) * 127.0);
+    }
+}
+
+// The Twister layout, until other controls are learned with L.
+fn midi_learn() -> Learn {
+    let ch1 = wmidi::Channel::Ch1;
+    Learn::new(&["speed", "opacity", "crunchiness", "red_w", "green_w", "blue_w"])
+        .bind("speed", ch1, twister_constants::ZERO_ZERO)
+        .bind("opacity", ch1, twister_constants::ZERO_ONE)
+        .bind("crunchiness", ch1, twister_constants::ZERO_TWO)
+        .bind("red_w", ch1, twister_constants::ONE_ZERO)
+        .bind("green_w", ch1, twister_constants::ONE_ONE)
+        .bind("blue_w", ch1, twister_constants::ONE_TWO)
+}
+
+fn apply_midi(params: &mut Params, learn: &mut Learn, event: &MidiEvent) {
+    match learn.feed(&event.message) {
+        Some(("speed", velocity)) => {
+            let v: u8 = velocity.into();
+            params.speed = util::speed_func(v as f32)
+        }
+        Some(("opacity", velocity)) => params.opacity = util::unipolar(velocity),
+        Some(("crunchiness", velocity)) => params.crunchiness = util::unipolar(velocity),
+        Some(("red_w", velocity)) => params.red_w = util::unipolar(velocity),
+        Some(("green_w", velocity)) => params.green_w = util::unipolar(velocity),
+        Some(("blue_w", velocity)) => params.blue_w = util::unipolar(velocity),
+        _ => {}
     }
 }
 
@@ -138,7 +241,7 @@ fn update_image(model: &mut Model) {
     let (w, h) = model.image.dimensions();
     for grid_x in 0..w {
         for grid_y in 0..h {
-            let should_crunch: bool = random_range(0.0, 1.0) <= model.crunchiness;
+            let should_crunch: bool = random_range(0.0, 1.0) <= model.params.crunchiness;
 
             let image_pixel = model.image.get_pixel(grid_x, grid_y);
 
@@ -156,9 +259,9 @@ fn update_image(model: &mut Model) {
                 let norm_green = green / unweighted_color_total;
                 let norm_blue = blue / unweighted_color_total;
 
-                let final_red = (norm_red * model.red_w) * luminosity * model.opacity;
-                let final_green = (norm_green * model.green_w) * luminosity * model.opacity;
-                let final_blue = (norm_blue * model.blue_w) * luminosity * model.opacity;
+                let final_red = (norm_red * model.params.red_w) * luminosity * model.params.opacity;
+                let final_green = (norm_green * model.params.green_w) * luminosity * model.params.opacity;
+                let final_blue = (norm_blue * model.params.blue_w) * luminosity * model.params.opacity;
 
                 if (rand_pick < final_red) {
                     model
@@ -196,6 +299,10 @@ fn view(app: &App, model: &Model, frame: Frame) {
         draw_frame_count(&frame, &draw, &win);
     }
 
+    if (model.learn.is_learning()) {
+        draw_learn_status(&model.learn, &draw, &win);
+    }
+
     // Write to the window frame.
     draw.to_frame(app, &frame).unwrap();
 
@@ -206,13 +313,22 @@ fn view(app: &App, model: &Model, frame: Frame) {
     }
 }
 
-fn key_pressed(_app: &App, model: &mut Model, key: Key) {
+fn key_pressed(app: &App, model: &mut Model, key: Key) {
     if key == Key::S {
         model.recording = !model.recording;
     }
+    if key == Key::M {
+        toggle_midi_recording(app, model);
+    }
     if key == Key::K {
         model.show_frame_count = !model.show_frame_count;
     }
+    model.learn.key_pressed(key);
+    model.simulator.key_pressed(key);
+}
+
+fn key_released(_app: &App, model: &mut Model, key: Key) {
+    model.simulator.key_released(key);
 }
 
 fn draw_frame_count(frame: &Frame, draw: &Draw, win: &Rect) {
@@ -222,3 +338,101 @@ fn draw_frame_count(frame: &Frame, draw: &Draw, win: &Rect) {
         .x_y(win.right() - 15 as f32, win.top() - 15 as f32)
         .color(WHITE);
 }
+
+fn draw_learn_status(learn: &Learn, draw: &Draw, win: &Rect) {
+    let status = format!(
+        "Turn a knob for {} ([ ] to pick another)",
+        learn.selected().unwrap_or_default()
+    );
+
+    draw.text(status.as_str())
+        .left_justify()
+        .w(400 as f32)
+        .x_y(win.left() + 210 as f32, win.top() - 15 as f32)
+        .color(WHITE);
+}
+
+fn toggle_midi_recording(app: &App, model: &mut Model) {
+    if (!model.midi_recorder.is_recording()) {
+        model.midi_recorder.start();
+        return;
+    }
+
+    model.midi_recorder.stop();
+    let path = "./output/".to_owned()
+        + &app.exe_name().unwrap()
+        + &app.elapsed_frames().to_string()
+        + ".mid";
+    if let Err(e) = model.midi_recorder.save(&path) {
+        eprintln!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sketches::midi::MockSource;
+    use wmidi::{Channel, ControlFunction};
+
+    fn apply_all(rx: Receiver<Event>, learn: &mut Learn) -> Params {
+        let mut params = Params::default();
+        let events = midi::drain_coalesced(&rx, twister::is_turn);
+        for event in events.into_iter().filter_map(Event::into_midi) {
+            apply_midi(&mut params, learn, &event);
+        }
+        params
+    }
+
+    fn apply_mock(source: MockSource) -> Params {
+        let (tx, rx) = channel();
+        source.start(tx).unwrap();
+        apply_all(rx, &mut midi_learn())
+    }
+
+    #[test]
+    fn zero_two_sets_crunchiness() {
+        let params = apply_mock(
+            MockSource::new()
+                .control_change(Channel::Ch1, twister_constants::ZERO_TWO, U7::MIN)
+                .control_change(Channel::Ch1, twister_constants::ZERO_TWO, U7::MAX),
+        );
+        assert_eq!(params.crunchiness, 1.0);
+    }
+
+    #[test]
+    fn other_channels_are_ignored() {
+        let params = apply_mock(
+            MockSource::new().control_change(Channel::Ch2, twister_constants::ZERO_TWO, U7::MIN),
+        );
+        assert_eq!(params.crunchiness, 1.0);
+    }
+
+    #[test]
+    fn simulator_turns_follow_the_defaults() {
+        let (tx, rx) = channel();
+        let _twister = simulator()
+            .turn(Encoder::new(0, 0, 1), -27)
+            .start(tx)
+            .unwrap();
+
+        let params = apply_all(rx, &mut midi_learn());
+        assert_eq!(params.opacity, 100.0 / 127.0);
+    }
+
+    #[test]
+    fn learned_control_replaces_the_default() {
+        let mut learn = midi_learn();
+        learn.select("crunchiness");
+        learn.start();
+
+        let (tx, rx) = channel();
+        MockSource::new()
+            .control_change(Channel::Ch5, ControlFunction(U7::from_u8_lossy(7)), U7::MIN)
+            .control_change(Channel::Ch1, twister_constants::ZERO_TWO, U7::MAX)
+            .start(tx)
+            .unwrap();
+
+        let params = apply_all(rx, &mut learn);
+        assert_eq!(params.crunchiness, 0.0);
+    }
+}
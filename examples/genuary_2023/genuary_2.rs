@@ -1,11 +1,10 @@
 #![allow(unused_parens)]
 
-use midir::MidiInputConnection;
 use nannou::prelude::*;
-use std::sync::mpsc::{channel, Receiver};
-use wmidi::MidiMessage;
+use std::time::{Duration, Instant};
 
-use sketches::midi;
+use sketches::midi::{self, Adsr, NoteTracker, QueuedInput};
+use sketches::util;
 
 fn main() {
     nannou::app(model).update(update).run();
@@ -13,9 +12,8 @@ fn main() {
 
 struct Model {
     show_frame_count: bool,
-    note_on_time: u64,
-    _connection: Option<MidiInputConnection<()>>,
-    receiver: Receiver<Vec<u8>>,
+    notes: NoteTracker,
+    midi: Option<QueuedInput>,
 }
 
 fn model(app: &App) -> Model {
@@ -27,34 +25,24 @@ fn model(app: &App) -> Model {
         .build()
         .unwrap();
 
-    let (tx, rx) = channel();
-
     Model {
         show_frame_count: false,
 
-        note_on_time: 0,
+        notes: NoteTracker::new(),
 
-        _connection: midi::init(tx),
-        receiver: rx,
+        midi: midi::init_queue().map_err(|e| eprintln!("{}", e)).ok(),
     }
 }
 
-fn update(app: &App, model: &mut Model, _update: Update) {
-    for data in model.receiver.try_iter() {
-        match MidiMessage::try_from(data.as_slice()) {
-            Err(e) => {
-                eprint!("Invalid midi message {:?}\n", e);
-            }
-            Ok(midi_message) => match midi_message {
-                wmidi::MidiMessage::NoteOn(_channel, _note, _velocity) => {
-                    model.note_on_time = app.elapsed_frames()
-                }
-                wmidi::MidiMessage::NoteOff(_channel, _note, _velocity) => {}
-                _ => {
-                    print!("Other message type\n")
-                }
-            },
-        };
+fn update(_app: &App, model: &mut Model, _update: Update) {
+    let midi = match &model.midi {
+        Some(midi) => midi,
+        None => return,
+    };
+    for event in midi.try_iter() {
+        if !model.notes.feed(&event.message) {
+            print!("Other message type\n")
+        }
     }
 }
 
@@ -68,6 +56,9 @@ fn view(app: &App, model: &Model, frame: Frame) {
     if (model.show_frame_count) {
         draw_frame_count(&frame, &draw, &win);
     }
+    if let Some(dropped) = model.midi.as_ref().map(QueuedInput::dropped).filter(|d| *d > 0) {
+        draw_dropped(dropped, &draw, &win);
+    }
 
     draw.ellipse()
         .x_y(0 as f32, 0 as f32)
@@ -86,15 +77,7 @@ fn view(app: &App, model: &Model, frame: Frame) {
         .stroke_weight(1 as f32)
         .color(BLACK);
 
-    let x_small = (model.note_on_time as f32 / 60.0).sin() * big_radius;
-    let y_small = (model.note_on_time as f32 / 60.0).cos() * big_radius;
-
-    draw.ellipse()
-        .x_y(x_small, y_small)
-        .radius(30 as f32)
-        .stroke(WHITE)
-        .stroke_weight(1 as f32)
-        .color(BLACK);
+    draw_notes(&model.notes, &draw, big_radius);
 
     let x = (frame.nth() as f32 / 60.0).sin() * big_radius;
     let y = (frame.nth() as f32 / 60.0).cos() * big_radius;
@@ -110,6 +93,33 @@ fn view(app: &App, model: &Model, frame: Frame) {
     draw.to_frame(app, &frame).unwrap();
 }
 
+const NOTE_ENVELOPE: Adsr = Adsr::new(
+    Duration::from_millis(20),
+    Duration::from_millis(200),
+    0.7,
+    Duration::from_millis(600),
+);
+
+// One circle per note, placed around the ring by pitch class and sized by
+// its envelope, so chords show up as several circles.
+fn draw_notes(notes: &NoteTracker, draw: &Draw, big_radius: f32) {
+    let now = Instant::now();
+    for note in notes.all() {
+        let level = note.envelope(&NOTE_ENVELOPE, now) * util::unipolar(note.velocity);
+        if level <= 0.0 {
+            continue;
+        }
+        let angle = (note.note as u8 % 12) as f32 / 12.0 * TAU;
+
+        draw.ellipse()
+            .x_y(angle.sin() * big_radius, angle.cos() * big_radius)
+            .radius(30 as f32 * level)
+            .stroke(WHITE)
+            .stroke_weight(1 as f32)
+            .color(BLACK);
+    }
+}
+
 fn key_pressed(app: &App, model: &mut Model, key: Key) {
     if key == Key::S {
         app.main_window()
@@ -127,3 +137,9 @@ fn draw_frame_count(frame: &Frame, draw: &Draw, win: &Rect) {
         .x_y(win.right() - 15 as f32, win.top() - 15 as f32)
         .color(WHITE);
 }
+
+fn draw_dropped(dropped: u64, draw: &Draw, win: &Rect) {
+    draw.text(&format!("dropped {}", dropped))
+        .x_y(win.left() + 50 as f32, win.top() - 15 as f32)
+        .color(RED);
+}
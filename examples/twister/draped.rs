@@ -1,15 +1,15 @@
 #![allow(unused_parens)]
 
-use sketches::midi;
+use sketches::midi::{self, Clock, Event, MidiConfig, MidiSource};
 use sketches::midi::twister::constants as twister_constants;
+use sketches::midi::twister::{
+    self, Accumulator, Encoder, Encoding, Simulator, TwisterEvent, VirtualTwister,
+};
+use sketches::util;
 
 use midir::MidiInputConnection;
 use nannou::prelude::*;
-use std::{
-    sync::mpsc::{channel, Receiver},
-    u8,
-};
-use wmidi::MidiMessage;
+use std::sync::mpsc::{channel, Receiver};
 
 fn main() {
     nannou::app(model).update(update).run();
@@ -18,9 +18,14 @@ fn main() {
 struct Model {
     show_frame_count: bool,
     speeds: [f32; 4],
+    // Only with relative encoders, absolute ones map straight to a speed.
+    speed_knobs: Option<Accumulator>,
     rad_positions: [f32; 4],
+    clock: Clock,
+    last_beat: f64,
     _connection: Option<MidiInputConnection<()>>,
-    receiver: Receiver<Vec<u8>>,
+    simulator: VirtualTwister,
+    receiver: Receiver<Event>,
 }
 
 fn model(app: &App) -> Model {
@@ -29,20 +34,80 @@ fn model(app: &App) -> Model {
         .size(1024, 1024)
         .view(view)
         .key_pressed(key_pressed)
+        .key_released(key_released)
         .build()
         .unwrap();
 
     let (tx, rx) = channel();
+    let encoding = encoding();
 
-    Model {
+    let model = Model {
         show_frame_count: false,
 
         speeds: [1.0, 1.0, 1.0, 1.0],
+        speed_knobs: speed_knobs(encoding),
 
         rad_positions: [0.0, 0.0, 0.0, 0.0],
-
-        _connection: midi::init(tx),
+        clock: Clock::new(),
+        last_beat: 0.0,
+
+        simulator: Simulator::new()
+            .encoding(encoding)
+            .step(5)
+            .start(tx.clone())
+            .unwrap(),
+        _connection: midi::init(tx).map_err(|e| eprintln!("{}", e)).ok(),
         receiver: rx,
+    };
+    sync_controller(&model);
+    model
+}
+
+// A factory Twister sends absolute values. Set the top row to "ENC 3FH/41H"
+// in the MIDI Fighter Utility and SKETCHES_TWISTER_ENCODING=relative, and
+// each click nudges the speed however far the knob has turned.
+fn encoding() -> Encoding {
+    match std::env::var("SKETCHES_TWISTER_ENCODING") {
+        Ok(value) if value.eq_ignore_ascii_case("relative") => Encoding::Relative,
+        _ => Encoding::Absolute,
+    }
+}
+
+fn speed_knobs(encoding: Encoding) -> Option<Accumulator> {
+    if encoding == Encoding::Absolute {
+        return None;
+    }
+    let mut knobs = Accumulator::new(Encoding::Relative)
+        .sensitivity(0.02)
+        .clamp(0.0, 20.0);
+    for col in 0..4 {
+        knobs.set(Encoder::new(0, 0, col), 1.0);
+    }
+    Some(knobs)
+}
+
+// A full sweep of an absolute ring covers speeds 1 to 11.
+fn absolute_speed(value: wmidi::U7) -> f32 {
+    1.0 + util::unipolar(value) * 10.0
+}
+
+// Point the rings at the starting speeds instead of wherever they were left.
+fn sync_controller(model: &Model) {
+    let relative = model.speed_knobs.is_some();
+    let ring = |speed: f32| if relative { speed / 20.0 } else { (speed - 1.0) / 10.0 };
+    let synced = MidiConfig::default().connect_output().and_then(|mut output| {
+        output.send_values(
+            wmidi::Channel::Ch1,
+            &[
+                (twister_constants::ZERO_ZERO, ring(model.speeds[0])),
+                (twister_constants::ZERO_ONE, ring(model.speeds[1])),
+                (twister_constants::ZERO_TWO, ring(model.speeds[2])),
+                (twister_constants::ZERO_THREE, ring(model.speeds[3])),
+            ],
+        )
+    });
+    if let Err(e) = synced {
+        eprintln!("{}", e);
     }
 }
 
@@ -86,6 +151,11 @@ fn key_pressed(app: &App, model: &mut Model, key: Key) {
     if key == Key::K {
         model.show_frame_count = !model.show_frame_count;
     }
+    model.simulator.key_pressed(key);
+}
+
+fn key_released(_app: &App, model: &mut Model, key: Key) {
+    model.simulator.key_released(key);
 }
 
 fn draw_frame_count(frame: &Frame, draw: &Draw, win: &Rect) {
@@ -97,44 +167,40 @@ fn draw_frame_count(frame: &Frame, draw: &Draw, win: &Rect) {
 }
 
 fn update_speeds(model: &mut Model) {
-    for data in model.receiver.try_iter() {
-        match MidiMessage::try_from(data.as_slice()) {
-            Err(e) => {
-                eprint!("Invalid midi message {:?}\n", e);
+    for event in model.receiver.try_iter().filter_map(Event::into_midi) {
+        if model.clock.feed(&event) {
+            continue;
+        }
+        let turned = match (twister::decode(&event.message), &mut model.speed_knobs) {
+            (Some(event), Some(knobs)) => knobs.feed(&event),
+            (Some(TwisterEvent::Turned { encoder, value }), None) => {
+                Some((encoder, absolute_speed(value)))
             }
-            Ok(midi_message) => match midi_message {
-                wmidi::MidiMessage::ControlChange(channel, note, velocity) => {
-                    match (channel, note) {
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_ZERO) => {
-                            let v: u8 = velocity.into();
-                            model.speeds[0] = 1.0 + ((v as f32) / 127.0) * 10.0
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_ONE) => {
-                            let v: u8 = velocity.into();
-                            model.speeds[1] = 1.0 + ((v as f32) / 127.0) * 10.0
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_TWO) => {
-                            let v: u8 = velocity.into();
-                            model.speeds[2] = 1.0 + ((v as f32) / 127.0) * 10.0
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_THREE) => {
-                            let v: u8 = velocity.into();
-                            model.speeds[3] = 1.0 + ((v as f32) / 127.0) * 10.0
-                        }
-                        _ => {}
-                    }
-                }
-                _ => {}
-            },
+            _ => None,
         };
+        if let Some((Encoder { bank: 0, row: 0, col }, speed)) = turned {
+            model.speeds[col as usize] = speed;
+        }
     }
 }
 
 const MOV_PER_SEC: f32 = 1.0;
+// Same as MOV_PER_SEC at 120 BPM.
+const MOV_PER_BEAT: f32 = 0.5;
 
+// Follows MIDI clock while a DAW or drum machine is playing, wall time
+// otherwise.
 fn update_positions(model: &mut Model, update_time: std::time::Duration) {
+    let beat = model.clock.position();
+    let update_len = if model.clock.is_playing() {
+        // Start rewinds the position, which shouldn't run the circles back.
+        (beat - model.last_beat).max(0.0) as f32 * MOV_PER_BEAT
+    } else {
+        (update_time.as_secs_f32() * MOV_PER_SEC)
+    };
+    model.last_beat = beat;
+
     for i in 0..model.rad_positions.len() {
-        let update_len = (update_time.as_secs_f32() * MOV_PER_SEC);
         model.rad_positions[i] += update_len * model.speeds[i]
     }
 }
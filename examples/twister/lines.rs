@@ -1,16 +1,20 @@
 #![allow(unused_parens)]
 extern crate sketches;
 
-use sketches::midi;
-use sketches::midi::twister::constants as twister_constants;
+use sketches::midi::playback::PLAYBACK_ENV_VAR;
+use sketches::midi::{Event, HighResDecoder, MidiConfig, MidiSource, Param, Playback};
+use sketches::midi::twister::{Encoder, Simulator, TwisterEvent, TwisterState, VirtualTwister};
+use sketches::util;
 
 use midir::MidiInputConnection;
 use nannou::prelude::*;
 use std::{
+    env,
     sync::mpsc::{channel, Receiver},
+    time::Duration,
     u8,
 };
-use wmidi::{MidiMessage, U7};
+use wmidi::U7;
 
 
 fn main() {
@@ -19,10 +23,13 @@ fn main() {
 
 struct Model {
     show_frame_count: bool,
-    offsets: [f32; 3],
-    points_mod: f32,
+    twister: TwisterState,
+    // Set by a 14-bit controller, until the matching knob is turned.
+    fine_offsets: [Option<f32>; 3],
+    playback: Option<Playback>,
     _connection: Option<MidiInputConnection<()>>,
-    receiver: Receiver<Vec<u8>>,
+    simulator: VirtualTwister,
+    receiver: Receiver<Event>,
 }
 
 fn model(app: &App) -> Model {
@@ -31,35 +38,103 @@ fn model(app: &App) -> Model {
         .size(1024, 1024)
         .view(view)
         .key_pressed(key_pressed)
+        .key_released(key_released)
         .build()
         .unwrap();
 
     let (tx, rx) = channel();
+    let twister = twister_state();
+    let simulator = Simulator::new().state(&twister).start(tx.clone()).unwrap();
+
+    let playback = env::var(PLAYBACK_ENV_VAR).ok().and_then(|path| {
+        Playback::open(path, tx.clone())
+            .map_err(|e| eprintln!("{}", e))
+            .ok()
+    });
+    let connection = match playback {
+        Some(_) => None,
+        None => MidiConfig::default()
+            .high_res(HighResDecoder::new())
+            .start(tx)
+            .map_err(|e| eprintln!("{}", e))
+            .ok(),
+    };
 
     Model {
         show_frame_count: false,
-        offsets: [0.0, 0.0, 0.0],
-        points_mod: 1.0,
-        _connection: midi::init(tx),
+        twister,
+        fine_offsets: [None; 3],
+        playback,
+        _connection: connection,
+        simulator,
         receiver: rx,
     }
 }
 
+// Lines start flat, with every point drawn.
+fn twister_state() -> TwisterState {
+    let mut state = TwisterState::new();
+    for col in 0..3 {
+        state.set(Encoder::new(0, 0, col), U7::from_u8_lossy(64));
+    }
+    state.set(Encoder::new(0, 3, 0), U7::MAX);
+    state
+}
+
+// Playback follows frames rather than wall time so a performance renders the
+// same however long each frame takes.
+const FRAME_TIME: Duration = Duration::from_micros(1_000_000 / 60);
+
 fn update(_app: &App, model: &mut Model, _update: Update) {
-    update_offsets(model);
+    if let Some(playback) = &mut model.playback {
+        playback.advance(FRAME_TIME);
+    }
+    for event in model.receiver.try_iter() {
+        match event {
+            Event::Midi(event) => {
+                if let Some(TwisterEvent::Turned { encoder, .. }) = model.twister.feed(&event.message) {
+                    if encoder.bank == 0 && encoder.row == 0 && encoder.col < 3 {
+                        model.fine_offsets[encoder.col as usize] = None;
+                    }
+                }
+            }
+            // NRPN 0-2 move the three offsets.
+            Event::HighRes(event) => match event.param {
+                Param::Nrpn(n) if n < 3 => {
+                    model.fine_offsets[n as usize] = Some(util::unipolar14(event.value) * 2.0 - 1.0);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
 }
 
+// Lets the offset knobs settle on flat without landing exactly on 64.
+const OFFSET_DEADZONE: f32 = 0.05;
+const OFFSET_KNOBS: [Encoder; 3] = [
+    Encoder::new(0, 0, 0),
+    Encoder::new(0, 0, 1),
+    Encoder::new(0, 0, 2),
+];
+const POINTS_KNOB: Encoder = Encoder::new(0, 3, 0);
+
 fn view(app: &App, model: &Model, frame: Frame) {
     let draw = app.draw();
     let win = app.window_rect();
 
     draw.background().color(BLACK);
 
-    let p_1 = pt2(-500.0, 0.0 + model.offsets[0] * 500.0);
-    let p_2 = pt2(0.0, 0.0 + model.offsets[1] * 500.0);
-    let p_3 = pt2(500.0, 0.0 + model.offsets[2] * 500.0);
+    let offset = |col: u8| {
+        model.fine_offsets[col as usize]
+            .unwrap_or_else(|| util::bipolar(model.twister.raw(OFFSET_KNOBS[col as usize]), OFFSET_DEADZONE))
+            * 500.0
+    };
+    let p_1 = pt2(-500.0, 0.0 + offset(0));
+    let p_2 = pt2(0.0, 0.0 + offset(1));
+    let p_3 = pt2(500.0, 0.0 + offset(2));
 
-    let num_points = ((model.points_mod * 20.0).floor() as i32);
+    let num_points = util::stepped(model.twister.raw(POINTS_KNOB), 21) as i32;
 
     for i in 0..num_points {
         let t_s = i as f32 / (num_points as f32); 
@@ -92,6 +167,11 @@ fn key_pressed(app: &App, model: &mut Model, key: Key) {
     if key == Key::K {
         model.show_frame_count = !model.show_frame_count;
     }
+    model.simulator.key_pressed(key);
+}
+
+fn key_released(_app: &App, model: &mut Model, key: Key) {
+    model.simulator.key_released(key);
 }
 
 fn draw_frame_count(frame: &Frame, draw: &Draw, win: &Rect) {
@@ -101,34 +181,3 @@ fn draw_frame_count(frame: &Frame, draw: &Draw, win: &Rect) {
         .x_y(win.right() - 15 as f32, win.top() - 15 as f32)
         .color(WHITE);
 }
-
-fn update_offsets(model: &mut Model) {
-    for data in model.receiver.try_iter() {
-        match MidiMessage::try_from(data.as_slice()) {
-            Err(e) => {
-                eprint!("Invalid midi message {:?}\n", e);
-            }
-            Ok(midi_message) => match midi_message {
-                wmidi::MidiMessage::ControlChange(channel, note, velocity) => {
-                    let v: f32 = (<U7 as Into<u8>>::into(velocity)) as f32;
-                    match (channel, note) {
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_ZERO) => {
-                            model.offsets[0] = 0.0 + ((v - 64.0) / 64.0)
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_ONE) => {
-                            model.offsets[1] = 0.0 + ((v - 64.0) / 64.0)
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_TWO) => {
-                            model.offsets[2] = 0.0 + ((v - 64.0) / 64.0)
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::THREE_ZERO) => {
-                            model.points_mod = 0.0 + (v / 127.0)
-                        }
-                        _ => {}
-                    }
-                }
-                _ => {}
-            },
-        };
-    }
-}
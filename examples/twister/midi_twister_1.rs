@@ -1,11 +1,14 @@
 #![allow(unused_parens)]
 
-use sketches::midi;
+use sketches::midi::{self, Adsr, Event, MidiConfig, NoteTracker, ParamStore};
+use sketches::util;
 
 use midir::MidiInputConnection;
 use nannou::prelude::*;
 use std::{sync::mpsc::{channel, Receiver}, u8};
-use wmidi::{ControlFunction, MidiMessage};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wmidi::{Channel, ControlFunction};
 
 fn main() {
     nannou::app(model).update(update).run();
@@ -13,10 +16,11 @@ fn main() {
 
 struct Model {
     show_frame_count: bool,
-    note_on_time: u64,
-    speed: f32,
-    _connection: Option<MidiInputConnection<()>>,
-    receiver: Receiver<Vec<u8>>,
+    notes: NoteTracker,
+    // Written by the twister's midir thread, read when drawing.
+    params: Arc<ParamStore>,
+    _connections: Vec<MidiInputConnection<()>>,
+    receiver: Receiver<Event>,
 }
 
 fn model(app: &App) -> Model {
@@ -29,43 +33,40 @@ fn model(app: &App) -> Model {
         .unwrap();
 
     let (tx, rx) = channel();
+    let params = Arc::new(ParamStore::new());
+    let inputs = [
+        MidiConfig::default().label("twister").params(params.clone()),
+        MidiConfig::new().env("SKETCHES_PADS_PORT").label("pads"),
+    ];
 
     Model {
         show_frame_count: false,
 
-        note_on_time: 0,
-        speed: 0.0,
+        notes: NoteTracker::new(),
+        params,
 
-        _connection: midi::init(tx),
+        _connections: midi::init_all(&inputs, tx)
+            .into_iter()
+            .filter_map(|c| c.map_err(|e| eprintln!("{}", e)).ok())
+            .collect(),
         receiver: rx,
     }
 }
 
-fn update(app: &App, model: &mut Model, _update: Update) {
-    for data in model.receiver.try_iter() {
-        match MidiMessage::try_from(data.as_slice()) {
-            Err(e) => {
-                eprint!("Invalid midi message {:?}\n", e);
+fn update(_app: &App, model: &mut Model, _update: Update) {
+    for event in model.receiver.try_iter().filter_map(Event::into_midi) {
+        if &*event.port == "pads" && model.notes.feed(&event.message) {
+            continue;
+        }
+        match (&*event.port, event.message) {
+            // The speed knob is read from `params` when drawing.
+            ("twister", wmidi::MidiMessage::ControlChange(channel, note, velocity)) => {
+                print!("CC {:?} {:?} {:?} \n", channel, note, velocity)
             }
-            Ok(midi_message) => match midi_message {
-                wmidi::MidiMessage::NoteOn(_channel, _note, _velocity) => {
-                    model.note_on_time = app.elapsed_frames()
-                }
-                wmidi::MidiMessage::ControlChange(channel, note, velocity) => {
-                    match (channel, note) {
-                        (wmidi::Channel::Ch1, ControlFunction::BANK_SELECT) => {
-                            let v: u8 = velocity.into();
-                            model.speed = v as f32 / 127.0
-                        }
-                        _ => {}
-                    }
-                    print!("CC {:?} {:?} {:?} \n", channel, note, velocity)
-                }
-                _ => {
-                    print!("Other message type\n")
-                }
-            },
-        };
+            (port, _) => {
+                print!("Other message type from {}\n", port)
+            }
+        }
     }
 }
 
@@ -97,18 +98,11 @@ fn view(app: &App, model: &Model, frame: Frame) {
         .stroke_weight(1 as f32)
         .color(BLACK);
 
-    let x_small = (model.note_on_time as f32 / 60.0).sin() * big_radius;
-    let y_small = (model.note_on_time as f32 / 60.0).cos() * big_radius;
-
-    draw.ellipse()
-        .x_y(x_small, y_small)
-        .radius(30 as f32)
-        .stroke(WHITE)
-        .stroke_weight(1 as f32)
-        .color(BLACK);
+    draw_notes(&model.notes, &draw, big_radius);
 
-    let x = (frame.nth() as f32 / (60.0 * model.speed)).sin() * big_radius;
-    let y = (frame.nth() as f32 / (60.0 * model.speed)).cos() * big_radius;
+    let speed = model.params.unipolar(Channel::Ch1, ControlFunction::BANK_SELECT);
+    let x = (frame.nth() as f32 / (60.0 * speed)).sin() * big_radius;
+    let y = (frame.nth() as f32 / (60.0 * speed)).cos() * big_radius;
 
     draw.ellipse()
         .x_y(x, y)
@@ -121,6 +115,33 @@ fn view(app: &App, model: &Model, frame: Frame) {
     draw.to_frame(app, &frame).unwrap();
 }
 
+const NOTE_ENVELOPE: Adsr = Adsr::new(
+    Duration::from_millis(20),
+    Duration::from_millis(200),
+    0.7,
+    Duration::from_millis(600),
+);
+
+// One circle per note, placed around the ring by pitch class and sized by
+// its envelope, so chords show up as several circles.
+fn draw_notes(notes: &NoteTracker, draw: &Draw, big_radius: f32) {
+    let now = Instant::now();
+    for note in notes.all() {
+        let level = note.envelope(&NOTE_ENVELOPE, now) * util::unipolar(note.velocity);
+        if level <= 0.0 {
+            continue;
+        }
+        let angle = (note.note as u8 % 12) as f32 / 12.0 * TAU;
+
+        draw.ellipse()
+            .x_y(angle.sin() * big_radius, angle.cos() * big_radius)
+            .radius(30 as f32 * level)
+            .stroke(WHITE)
+            .stroke_weight(1 as f32)
+            .color(BLACK);
+    }
+}
+
 fn key_pressed(app: &App, model: &mut Model, key: Key) {
     if key == Key::S {
         app.main_window()
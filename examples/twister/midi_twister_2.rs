@@ -1,16 +1,14 @@
 #![allow(unused_parens)]
 extern crate sketches;
 
-use sketches::midi;
-use sketches::midi::twister::constants as twister_constants;
+use sketches::midi::{self, Event, MidiSource};
+use sketches::midi::twister::{Encoder, Simulator, TwisterEvent, TwisterState, VirtualTwister};
 
-use midir::MidiInputConnection;
 use nannou::prelude::*;
 use std::{
     sync::mpsc::{channel, Receiver},
     u8,
 };
-use wmidi::MidiMessage;
 
 
 fn main() {
@@ -20,9 +18,12 @@ fn main() {
 struct Model {
     show_frame_count: bool,
     speeds: [f32; 4],
+    twister: TwisterState,
     rad_positions: [f32; 4],
-    _connection: Option<MidiInputConnection<()>>,
-    receiver: Receiver<Vec<u8>>,
+    controller: Option<String>,
+    _supervisor: midi::Supervisor,
+    simulator: VirtualTwister,
+    receiver: Receiver<Event>,
 }
 
 fn model(app: &App) -> Model {
@@ -31,6 +32,7 @@ fn model(app: &App) -> Model {
         .size(1024, 1024)
         .view(view)
         .key_pressed(key_pressed)
+        .key_released(key_released)
         .build()
         .unwrap();
 
@@ -40,10 +42,13 @@ fn model(app: &App) -> Model {
         show_frame_count: false,
 
         speeds: [1.0, 1.0, 1.0, 1.0],
+        twister: TwisterState::new(),
 
         rad_positions: [0.0, 0.0, 0.0, 0.0],
 
-        _connection: midi::init(tx),
+        controller: None,
+        simulator: Simulator::new().start(tx.clone()).unwrap(),
+        _supervisor: midi::supervise(tx),
         receiver: rx,
     }
 }
@@ -64,6 +69,8 @@ fn view(app: &App, model: &Model, frame: Frame) {
         draw_frame_count(&frame, &draw, &win);
     }
 
+    draw_controller_status(model, &draw, &win);
+
     for i in 0..model.rad_positions.len() {
         let x = (model.rad_positions[i]).sin() * (big_radius * ((1.0 + i as f32) * 0.25));
         let y = (model.rad_positions[i]).cos() * (big_radius * ((1.0 + i as f32) * 0.25));
@@ -88,6 +95,11 @@ fn key_pressed(app: &App, model: &mut Model, key: Key) {
     if key == Key::K {
         model.show_frame_count = !model.show_frame_count;
     }
+    model.simulator.key_pressed(key);
+}
+
+fn key_released(_app: &App, model: &mut Model, key: Key) {
+    model.simulator.key_released(key);
 }
 
 fn draw_frame_count(frame: &Frame, draw: &Draw, win: &Rect) {
@@ -98,37 +110,44 @@ fn draw_frame_count(frame: &Frame, draw: &Draw, win: &Rect) {
         .color(WHITE);
 }
 
+fn draw_controller_status(model: &Model, draw: &Draw, win: &Rect) {
+    let status = match &model.controller {
+        Some(port) => port.clone(),
+        None => "No controller".to_owned(),
+    };
+
+    draw.text(status.as_str())
+        .left_justify()
+        .w(300 as f32)
+        .x_y(win.left() + 160 as f32, win.top() - 15 as f32)
+        .color(GRAY);
+}
+
+// Same as Key::K, from the bottom right encoder of the first bank.
+const FRAME_COUNT_TOGGLE: Encoder = Encoder::new(0, 3, 3);
+
 fn update_speeds(model: &mut Model) {
-    for data in model.receiver.try_iter() {
-        match MidiMessage::try_from(data.as_slice()) {
-            Err(e) => {
-                eprint!("Invalid midi message {:?}\n", e);
+    for event in model.receiver.try_iter() {
+        match event {
+            // The simulator announces itself too, but it isn't the controller.
+            Event::Connected(port) if &*port != model.simulator.port() => {
+                model.controller = Some(port.to_string())
+            }
+            Event::Disconnected(port) if model.controller.as_deref() == Some(&*port) => {
+                model.controller = None
             }
-            Ok(midi_message) => match midi_message {
-                wmidi::MidiMessage::ControlChange(channel, note, velocity) => {
-                    match (channel, note) {
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_ZERO) => {
-                            let v: u8 = velocity.into();
-                            model.speeds[0] = 1.0 + ((v as f32) / 127.0) * 10.0
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_ONE) => {
-                            let v: u8 = velocity.into();
-                            model.speeds[1] = 1.0 + ((v as f32) / 127.0) * 10.0
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_TWO) => {
-                            let v: u8 = velocity.into();
-                            model.speeds[2] = 1.0 + ((v as f32) / 127.0) * 10.0
-                        }
-                        (wmidi::Channel::Ch1, twister_constants::ZERO_THREE) => {
-                            let v: u8 = velocity.into();
-                            model.speeds[3] = 1.0 + ((v as f32) / 127.0) * 10.0
-                        }
-                        _ => {}
-                    }
+            Event::Midi(event) => match model.twister.feed(&event.message) {
+                Some(TwisterEvent::EncoderPressed(FRAME_COUNT_TOGGLE)) => {
+                    model.show_frame_count = !model.show_frame_count;
                 }
                 _ => {}
             },
-        };
+            _ => {}
+        }
+    }
+
+    for (col, speed) in model.speeds.iter_mut().enumerate() {
+        *speed = 1.0 + model.twister.get(Encoder::new(0, 0, col as u8)) * 10.0;
     }
 }
 
@@ -0,0 +1,216 @@
+use std::time::Instant;
+use wmidi::MidiMessage;
+
+use super::event::MidiEvent;
+
+// MIDI clock sends 24 ticks per quarter note, song position counts 16ths.
+pub const PPQN: u32 = 24;
+const TICKS_PER_SIXTEENTH: u64 = 6;
+// Longer gaps mean the clock stopped and started again, so they don't count
+// towards the tempo.
+const MAX_TICK_US: f64 = 250_000.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Stopped,
+    Playing,
+}
+
+// Follows clock, Start, Stop, Continue and Song Position messages from a DAW
+// or drum machine. Tempo is estimated from the spacing of ticks whether or
+// not the transport is running, the position only moves while playing.
+#[derive(Clone, Debug)]
+pub struct Clock {
+    transport: Transport,
+    beats_per_bar: u32,
+    smoothing: f64,
+    ticks: u64,
+    last_tick_us: Option<u64>,
+    last_tick_at: Option<Instant>,
+    tick_us: Option<f64>,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock {
+            transport: Transport::Stopped,
+            beats_per_bar: 4,
+            smoothing: 0.1,
+            ticks: 0,
+            last_tick_us: None,
+            last_tick_at: None,
+            tick_us: None,
+        }
+    }
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Clock::default()
+    }
+
+    pub fn beats_per_bar(mut self, beats: u32) -> Self {
+        self.beats_per_bar = beats.max(1);
+        self
+    }
+
+    // How much each tick moves the tempo estimate, 0..1. Lower is steadier
+    // but slower to follow tempo changes.
+    pub fn smoothing(mut self, smoothing: f64) -> Self {
+        self.smoothing = smoothing.clamp(0.001, 1.0);
+        self
+    }
+
+    // Returns whether the event was a clock message.
+    pub fn feed(&mut self, event: &MidiEvent) -> bool {
+        match event.message {
+            MidiMessage::TimingClock => self.tick(event.timestamp_us),
+            MidiMessage::Start => {
+                self.ticks = 0;
+                self.last_tick_at = None;
+                self.transport = Transport::Playing;
+            }
+            MidiMessage::Continue => self.transport = Transport::Playing,
+            MidiMessage::Stop => self.transport = Transport::Stopped,
+            MidiMessage::SongPositionPointer(position) => {
+                self.ticks = u16::from(position) as u64 * TICKS_PER_SIXTEENTH;
+                self.last_tick_at = None;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn tick(&mut self, timestamp_us: u64) {
+        if let Some(last) = self.last_tick_us.replace(timestamp_us) {
+            let interval = timestamp_us.saturating_sub(last) as f64;
+            if interval > 0.0 && interval < MAX_TICK_US {
+                self.tick_us = Some(match self.tick_us {
+                    Some(smoothed) => smoothed + (interval - smoothed) * self.smoothing,
+                    None => interval,
+                });
+            }
+        }
+        if self.transport == Transport::Playing {
+            // The first tick after Start or a new song position is that
+            // position, not the one after it.
+            if self.last_tick_at.is_some() {
+                self.ticks += 1;
+            }
+            self.last_tick_at = Some(Instant::now());
+        }
+    }
+
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.transport == Transport::Playing
+    }
+
+    pub fn bpm(&self) -> Option<f64> {
+        self.tick_us.map(|us| 60_000_000.0 / (us * PPQN as f64))
+    }
+
+    // Beats since Start. Between ticks the position is moved on at the
+    // current tempo, at most up to where the next tick is due, so it doesn't
+    // step at frame rate.
+    pub fn position(&self) -> f64 {
+        let between = match (self.is_playing(), self.last_tick_at, self.tick_us) {
+            (true, Some(at), Some(us)) => (at.elapsed().as_micros() as f64 / us).min(1.0),
+            _ => 0.0,
+        };
+        (self.ticks as f64 + between) / PPQN as f64
+    }
+
+    // 0..1 through the current beat.
+    pub fn beat_phase(&self) -> f64 {
+        self.position().fract()
+    }
+
+    // 0..1 through the current bar.
+    pub fn bar_phase(&self) -> f64 {
+        (self.position() / self.beats_per_bar as f64).fract()
+    }
+
+    // Counts from 0.
+    pub fn beat_in_bar(&self) -> u32 {
+        (self.position() as u64 % self.beats_per_bar as u64) as u32
+    }
+
+    pub fn bar(&self) -> u64 {
+        self.position() as u64 / self.beats_per_bar as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use wmidi::U14;
+
+    // 120 BPM.
+    const TICK_US: u64 = 500_000 / PPQN as u64;
+
+    fn event(timestamp_us: u64, message: MidiMessage<'static>) -> MidiEvent {
+        MidiEvent {
+            timestamp_us,
+            port: Arc::from("clock"),
+            message,
+        }
+    }
+
+    fn ticks(clock: &mut Clock, from: u64, count: u64) -> u64 {
+        for i in 0..count {
+            clock.feed(&event((from + i) * TICK_US, MidiMessage::TimingClock));
+        }
+        from + count
+    }
+
+    #[test]
+    fn bpm_from_tick_spacing() {
+        let mut clock = Clock::new();
+        assert_eq!(clock.bpm(), None);
+        ticks(&mut clock, 0, PPQN as u64);
+        let bpm = clock.bpm().unwrap();
+        assert!((bpm - 120.0).abs() < 0.1, "{}", bpm);
+        // Stopped, so the position doesn't move.
+        assert_eq!(clock.position(), 0.0);
+    }
+
+    #[test]
+    fn start_and_stop() {
+        let mut clock = Clock::new().beats_per_bar(4);
+        clock.feed(&event(0, MidiMessage::Start));
+        assert!(clock.is_playing());
+        // The first tick is beat 0, so this ends on the first tick of beat 5.
+        let at = ticks(&mut clock, 0, 5 * PPQN as u64 + 1);
+        clock.feed(&event(at * TICK_US, MidiMessage::Stop));
+        assert_eq!(clock.transport(), Transport::Stopped);
+        assert_eq!(clock.position(), 5.0);
+        assert_eq!(clock.bar(), 1);
+        assert_eq!(clock.beat_in_bar(), 1);
+
+        ticks(&mut clock, at, PPQN as u64);
+        assert_eq!(clock.position(), 5.0);
+
+        clock.feed(&event(0, MidiMessage::Start));
+        ticks(&mut clock, 0, 1);
+        assert!(clock.position() <= 1.0 / PPQN as f64);
+    }
+
+    #[test]
+    fn song_position_moves_the_position() {
+        let mut clock = Clock::new();
+        // Song position counts 16ths, so 8 is the start of beat 2.
+        let position = U14::try_from(8).unwrap();
+        clock.feed(&event(0, MidiMessage::SongPositionPointer(position)));
+        assert_eq!(clock.position(), 2.0);
+
+        clock.feed(&event(0, MidiMessage::Continue));
+        ticks(&mut clock, 0, PPQN as u64 + 1);
+        clock.feed(&event(0, MidiMessage::Stop));
+        assert_eq!(clock.position(), 3.0);
+    }
+}
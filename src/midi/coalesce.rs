@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use wmidi::{Channel, ControlFunction, MidiMessage};
+
+use super::event::Event;
+use super::high_res::Param;
+
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Cc(Arc<str>, Channel, ControlFunction),
+    HighRes(Arc<str>, Channel, Param),
+}
+
+fn key<F>(event: &Event, continuous: &F) -> Option<Key>
+where
+    F: Fn(Channel, ControlFunction) -> bool,
+{
+    match event {
+        Event::Midi(event) => match event.message {
+            MidiMessage::ControlChange(channel, control, _) if continuous(channel, control) => {
+                Some(Key::Cc(event.port.clone(), channel, control))
+            }
+            _ => None,
+        },
+        Event::HighRes(event) => Some(Key::HighRes(event.port.clone(), event.channel, event.param)),
+        _ => None,
+    }
+}
+
+// Keeps only the last value of each high resolution parameter and of each
+// control change `continuous` accepts, per port and channel. It stays where
+// that last value was in the stream. Everything else, including buttons sent
+// as control changes, is kept in order, so a press and release within one
+// frame both arrive. `twister::is_turn` picks out the Twister's encoders.
+pub fn coalesce<I, F>(events: I, continuous: F) -> Vec<Event>
+where
+    I: IntoIterator<Item = Event>,
+    F: Fn(Channel, ControlFunction) -> bool,
+{
+    let events: Vec<Event> = events.into_iter().collect();
+    let mut last = HashMap::new();
+    for (i, event) in events.iter().enumerate() {
+        if let Some(key) = key(event, &continuous) {
+            last.insert(key, i);
+        }
+    }
+    events
+        .into_iter()
+        .enumerate()
+        .filter(|(i, event)| key(event, &continuous).is_none_or(|key| last[&key] == *i))
+        .map(|(_, event)| event)
+        .collect()
+}
+
+// Everything received since the last call, coalesced. Call once per frame
+// so a fast sweep only applies where the knob ended up.
+pub fn drain_coalesced<F>(receiver: &Receiver<Event>, continuous: F) -> Vec<Event>
+where
+    F: Fn(Channel, ControlFunction) -> bool,
+{
+    coalesce(receiver.try_iter(), continuous)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::event::MidiEvent;
+    use crate::midi::twister;
+
+    fn events(messages: &[&[u8]]) -> Vec<Event> {
+        let port: Arc<str> = Arc::from("test");
+        messages
+            .iter()
+            .map(|bytes| Event::Midi(MidiEvent::decode(0, &port, bytes).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_last_value_where_it_was() {
+        let sweep = events(&[
+            &[0xB0, 1, 1],
+            &[0x90, 60, 100],
+            &[0xB0, 2, 5],
+            &[0xB0, 1, 9],
+            &[0x80, 60, 0],
+        ]);
+        let expected = events(&[&[0x90, 60, 100], &[0xB0, 2, 5], &[0xB0, 1, 9], &[0x80, 60, 0]]);
+        assert_eq!(coalesce(sweep, |_, _| true), expected);
+    }
+
+    #[test]
+    fn keeps_non_cc_order() {
+        let notes = events(&[&[0x90, 60, 100], &[0x91, 60, 100], &[0x80, 60, 0], &[0xF8]]);
+        assert_eq!(coalesce(notes.clone(), |_, _| true), notes);
+    }
+
+    #[test]
+    fn press_and_release_both_arrive() {
+        // Encoder 0 pressed and released on the switch channel while turning.
+        let frame = events(&[
+            &[0xB0, 0, 10],
+            &[0xB1, 0, 127],
+            &[0xB0, 0, 11],
+            &[0xB1, 0, 0],
+            &[0xB0, 0, 12],
+        ]);
+        let expected = events(&[&[0xB1, 0, 127], &[0xB1, 0, 0], &[0xB0, 0, 12]]);
+        assert_eq!(coalesce(frame, twister::is_turn), expected);
+    }
+}
@@ -0,0 +1,309 @@
+use midir::{Ignore, MidiIO, MidiInput, MidiInputConnection};
+use regex::Regex;
+use std::env;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::error::Error;
+use super::event::{Diagnostic, Event, MidiEvent};
+use super::high_res::HighResDecoder;
+use super::output::Output;
+use super::params::{ParamInput, ParamStore};
+use super::queue::{EventQueue, Overflow, QueuedInput};
+
+pub const DEFAULT_PORT: &str = "Fighter";
+pub const PORT_ENV_VAR: &str = "SKETCHES_MIDI_PORT";
+pub const VIRTUAL_ENV_VAR: &str = "SKETCHES_MIDI_VIRTUAL";
+
+#[derive(Clone, Debug)]
+pub enum PortSelector {
+    Name(String),
+    Contains(String),
+    Regex(Regex),
+    Index(usize),
+    // Reads the variable at connect time. A number is treated as an index,
+    // anything else as a substring of the port name.
+    Env(String),
+}
+
+impl PortSelector {
+    fn find(&self, names: &[String]) -> Option<usize> {
+        match self {
+            PortSelector::Name(name) => names.iter().position(|n| n == name),
+            PortSelector::Contains(part) => names.iter().position(|n| n.contains(part.as_str())),
+            PortSelector::Regex(re) => names.iter().position(|n| re.is_match(n)),
+            PortSelector::Index(i) => (*i < names.len()).then_some(*i),
+            PortSelector::Env(var) => {
+                let value = env::var(var).ok()?;
+                match value.parse::<usize>() {
+                    Ok(i) => PortSelector::Index(i).find(names),
+                    Err(_) => PortSelector::Contains(value).find(names),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MidiConfig {
+    pub(crate) client_name: String,
+    label: Option<String>,
+    selectors: Vec<PortSelector>,
+    virtual_port: Option<String>,
+    diagnostics: Option<Sender<Diagnostic>>,
+    high_res: Option<HighResDecoder>,
+    params: Option<Arc<ParamStore>>,
+    pub(crate) poll_interval: Duration,
+}
+
+impl Default for MidiConfig {
+    fn default() -> Self {
+        let config = MidiConfig::new().env(PORT_ENV_VAR).contains(DEFAULT_PORT);
+        match env::var(VIRTUAL_ENV_VAR) {
+            Ok(name) => config.virtual_port(&name),
+            Err(_) => config,
+        }
+    }
+}
+
+impl MidiConfig {
+    pub fn new() -> Self {
+        MidiConfig {
+            client_name: "reading input".to_owned(),
+            label: None,
+            selectors: Vec::new(),
+            virtual_port: None,
+            diagnostics: None,
+            high_res: None,
+            params: None,
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+
+    pub fn client_name(mut self, name: &str) -> Self {
+        self.client_name = name.to_owned();
+        self
+    }
+
+    // Tags events from this input with `label` instead of the port name, so
+    // merged streams from several devices can be told apart.
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    // Selectors are tried in the order they were added, the first one that
+    // matches a port wins.
+    pub fn select(mut self, selector: PortSelector) -> Self {
+        self.selectors.push(selector);
+        self
+    }
+
+    pub fn name(self, name: &str) -> Self {
+        self.select(PortSelector::Name(name.to_owned()))
+    }
+
+    pub fn contains(self, part: &str) -> Self {
+        self.select(PortSelector::Contains(part.to_owned()))
+    }
+
+    pub fn regex(self, re: Regex) -> Self {
+        self.select(PortSelector::Regex(re))
+    }
+
+    pub fn index(self, index: usize) -> Self {
+        self.select(PortSelector::Index(index))
+    }
+
+    pub fn env(self, var: &str) -> Self {
+        self.select(PortSelector::Env(var.to_owned()))
+    }
+
+    // Creates a port other software can connect to instead of looking for
+    // one. Selectors are ignored. Only available on unix.
+    pub fn virtual_port(mut self, name: &str) -> Self {
+        self.virtual_port = Some(name.to_owned());
+        self
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        self.virtual_port.is_some()
+    }
+
+    // Undecodable messages go here instead of being printed to stderr.
+    pub fn diagnostics(mut self, tx: Sender<Diagnostic>) -> Self {
+        self.diagnostics = Some(tx);
+        self
+    }
+
+    // Also sends `Event::HighRes` for the 14-bit values `decoder` puts
+    // together. Each connection starts with its own copy.
+    pub fn high_res(mut self, decoder: HighResDecoder) -> Self {
+        self.high_res = Some(decoder);
+        self
+    }
+
+    // Also writes control changes into `store` from the midir thread, so a
+    // sketch can read knobs from it while notes still come through events.
+    pub fn params(mut self, store: Arc<ParamStore>) -> Self {
+        self.params = Some(store);
+        self
+    }
+
+    // How often a supervised connection checks the port list.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    pub fn find_port(&self, names: &[String]) -> Option<usize> {
+        self.selectors.iter().find_map(|s| s.find(names))
+    }
+
+    pub fn connect(&self, tx: Sender<Event>) -> Result<MidiInputConnection<()>, Error> {
+        self.open(tx).map(|(connection, _, _)| connection)
+    }
+
+    pub fn connect_output(&self) -> Result<Output, Error> {
+        Output::connect(self)
+    }
+
+    // Reads into a bounded queue instead of a channel. The midir thread
+    // never allocates or blocks, see `EventQueue`.
+    pub fn connect_queue(&self, capacity: usize, overflow: Overflow) -> Result<QueuedInput, Error> {
+        let queue = Arc::new(EventQueue::new(capacity, overflow));
+        let writer = queue.clone();
+        let (connection, _, port) = self
+            .open_with(|_| move |stamp: u64, bytes: &[u8], _: &mut ()| writer.push(stamp, bytes))?;
+        Ok(QueuedInput::new(connection, queue, port))
+    }
+
+    // Writes control changes straight into `store` from the midir thread.
+    // Everything else is ignored.
+    pub fn connect_params(&self, store: Arc<ParamStore>) -> Result<ParamInput, Error> {
+        let writer = store.clone();
+        let (connection, _, port) = self.open_with(|_| {
+            move |_: u64, bytes: &[u8], _: &mut ()| {
+                writer.write(bytes);
+            }
+        })?;
+        Ok(ParamInput::new(connection, store, port))
+    }
+
+    // Returns the connection, the name of the port it opened and the name its
+    // events are tagged with.
+    pub(crate) fn open(
+        &self,
+        tx: Sender<Event>,
+    ) -> Result<(MidiInputConnection<()>, String, Arc<str>), Error> {
+        self.open_with(|port| self.callback(port, tx))
+    }
+
+    fn open_with<F>(
+        &self,
+        callback: impl FnOnce(Arc<str>) -> F,
+    ) -> Result<(MidiInputConnection<()>, String, Arc<str>), Error>
+    where
+        F: FnMut(u64, &[u8], &mut ()) + Send + 'static,
+    {
+        let mut midi_in = MidiInput::new(&self.client_name)?;
+        midi_in.ignore(Ignore::None);
+        if let Some(name) = &self.virtual_port {
+            return self.open_virtual(midi_in, name, callback);
+        }
+        let ports = midi_in.ports();
+        let names = port_names(&midi_in)?;
+
+        let i = self.find_port(&names).ok_or_else(|| Error::PortNotFound {
+            available: names.clone(),
+        })?;
+        let port: Arc<str> = Arc::from(self.label.as_deref().unwrap_or(&names[i]));
+        let connection = midi_in
+            .connect(&ports[i], "midir-read-input", callback(port.clone()), ())
+            .map_err(|e| Error::Connect {
+                port: names[i].clone(),
+                kind: e.kind(),
+            })?;
+        Ok((connection, names[i].clone(), port))
+    }
+
+    #[cfg(unix)]
+    fn open_virtual<F>(
+        &self,
+        midi_in: MidiInput,
+        name: &str,
+        callback: impl FnOnce(Arc<str>) -> F,
+    ) -> Result<(MidiInputConnection<()>, String, Arc<str>), Error>
+    where
+        F: FnMut(u64, &[u8], &mut ()) + Send + 'static,
+    {
+        use midir::os::unix::VirtualInput;
+
+        let port: Arc<str> = Arc::from(self.label.as_deref().unwrap_or(name));
+        let connection = midi_in
+            .create_virtual(name, callback(port.clone()), ())
+            .map_err(|e| Error::Connect {
+                port: name.to_owned(),
+                kind: e.kind(),
+            })?;
+        Ok((connection, name.to_owned(), port))
+    }
+
+    #[cfg(not(unix))]
+    fn open_virtual<F>(
+        &self,
+        _midi_in: MidiInput,
+        name: &str,
+        _callback: impl FnOnce(Arc<str>) -> F,
+    ) -> Result<(MidiInputConnection<()>, String, Arc<str>), Error>
+    where
+        F: FnMut(u64, &[u8], &mut ()) + Send + 'static,
+    {
+        Err(Error::VirtualUnsupported(name.to_owned()))
+    }
+
+    fn callback(
+        &self,
+        port: Arc<str>,
+        tx: Sender<Event>,
+    ) -> impl FnMut(u64, &[u8], &mut ()) + Send + 'static {
+        let diagnostics = self.diagnostics.clone();
+        let mut high_res = self.high_res.clone();
+        let params = self.params.clone();
+        move |stamp, bytes, _| {
+            if let Some(store) = &params {
+                store.write(bytes);
+            }
+            match MidiEvent::decode(stamp, &port, bytes) {
+                // A sketch that dropped its receiver is shutting down, not an
+                // error worth taking the midir thread down for.
+                Ok(event) => {
+                    let decoded = high_res.as_mut().and_then(|d| d.feed_event(&event));
+                    let _ = tx.send(Event::Midi(event));
+                    if let Some(decoded) = decoded {
+                        let _ = tx.send(Event::HighRes(decoded));
+                    }
+                }
+                Err(diagnostic) => report(&diagnostics, diagnostic),
+            }
+        }
+    }
+}
+
+pub(crate) fn port_names<T: MidiIO>(midi_io: &T) -> Result<Vec<String>, Error> {
+    midi_io
+        .ports()
+        .iter()
+        .map(|p| midi_io.port_name(p).map_err(Error::from))
+        .collect()
+}
+
+fn report(diagnostics: &Option<Sender<Diagnostic>>, diagnostic: Diagnostic) {
+    match diagnostics {
+        Some(tx) => {
+            let _ = tx.send(diagnostic);
+        }
+        None => eprintln!("Invalid midi message {:?}", diagnostic),
+    }
+}
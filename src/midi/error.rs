@@ -0,0 +1,84 @@
+use midir::{ConnectErrorKind, InitError, PortInfoError, SendError};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Init(InitError),
+    PortNotFound {
+        available: Vec<String>,
+    },
+    PortName(PortInfoError),
+    Connect {
+        port: String,
+        kind: ConnectErrorKind,
+    },
+    Send(SendError),
+    VirtualUnsupported(String),
+    Io(std::io::Error),
+    Smf(midly::Error),
+    Profile(toml::de::Error),
+    InvalidProfile {
+        profile: String,
+        reason: String,
+    },
+    Bindings(toml::de::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Init(e) => write!(f, "Could not start midi: {}", e),
+            Error::PortNotFound { available } => {
+                write!(f, "No midi port :( available: {:?}", available)
+            }
+            Error::PortName(e) => write!(f, "Could not read midi port name: {}", e),
+            Error::Connect { port, kind } => {
+                write!(f, "Could not connect to midi port {:?}: {}", port, kind)
+            }
+            Error::Send(e) => write!(f, "Could not send midi message: {}", e),
+            Error::VirtualUnsupported(port) => {
+                write!(f, "Virtual midi port {:?} is not supported here", port)
+            }
+            Error::Io(e) => write!(f, "Could not access file: {}", e),
+            Error::Smf(e) => write!(f, "Could not read midi file: {}", e),
+            Error::Profile(e) => write!(f, "Could not read controller profile: {}", e),
+            Error::InvalidProfile { profile, reason } => {
+                write!(f, "Invalid controller profile {:?}: {}", profile, reason)
+            }
+            Error::Bindings(e) => write!(f, "Could not read midi bindings: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Init(e) => Some(e),
+            Error::PortName(e) => Some(e),
+            Error::Send(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Smf(e) => Some(e),
+            Error::Profile(e) => Some(e),
+            Error::Bindings(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<InitError> for Error {
+    fn from(e: InitError) -> Self {
+        Error::Init(e)
+    }
+}
+
+impl From<PortInfoError> for Error {
+    fn from(e: PortInfoError) -> Self {
+        Error::PortName(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,61 @@
+use std::sync::Arc;
+use wmidi::{FromBytesError, MidiMessage};
+
+use super::high_res::HighResEvent;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MidiEvent {
+    // Microseconds as reported by midir, the origin depends on the backend.
+    pub timestamp_us: u64,
+    pub port: Arc<str>,
+    pub message: MidiMessage<'static>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Midi(MidiEvent),
+    // Sent after the message that completed it, see `MidiConfig::high_res`.
+    HighRes(HighResEvent),
+    Connected(Arc<str>),
+    Disconnected(Arc<str>),
+}
+
+impl Event {
+    pub fn into_midi(self) -> Option<MidiEvent> {
+        match self {
+            Event::Midi(event) => Some(event),
+            _ => None,
+        }
+    }
+}
+
+// Bytes that could not be decoded into a `MidiMessage`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub timestamp_us: u64,
+    pub port: Arc<str>,
+    pub bytes: Vec<u8>,
+    pub error: FromBytesError,
+}
+
+impl MidiEvent {
+    pub fn decode(
+        timestamp_us: u64,
+        port: &Arc<str>,
+        bytes: &[u8],
+    ) -> Result<MidiEvent, Diagnostic> {
+        match MidiMessage::try_from(bytes) {
+            Ok(message) => Ok(MidiEvent {
+                timestamp_us,
+                port: port.clone(),
+                message: message.to_owned(),
+            }),
+            Err(error) => Err(Diagnostic {
+                timestamp_us,
+                port: port.clone(),
+                bytes: Vec::from(bytes),
+                error,
+            }),
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::sync::Arc;
+use wmidi::{Channel, ControlFunction, MidiMessage, U14, U7};
+
+use super::event::MidiEvent;
+
+const NRPN_MSB: u8 = 99;
+const NRPN_LSB: u8 = 98;
+const RPN_MSB: u8 = 101;
+const RPN_LSB: u8 = 100;
+const DATA_ENTRY_MSB: u8 = 6;
+const DATA_ENTRY_LSB: u8 = 38;
+const DATA_INCREMENT: u8 = 96;
+const DATA_DECREMENT: u8 = 97;
+// Selecting RPN 127/127 deselects the parameter, so stray data entry after a
+// parameter change doesn't land on it.
+const RPN_NULL: u16 = 0x3FFF;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Param {
+    // The MSB control of a CC n / n+32 pair.
+    Cc(ControlFunction),
+    Nrpn(u16),
+    Rpn(u16),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HighResEvent {
+    pub timestamp_us: u64,
+    pub port: Arc<str>,
+    pub channel: Channel,
+    pub param: Param,
+    pub value: U14,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+enum Selecting {
+    #[default]
+    None,
+    Nrpn(u8, u8),
+    Rpn(u8, u8),
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct ChannelState {
+    cc_msb: [u8; 32],
+    selecting: Selecting,
+    data: u16,
+}
+
+// Combines the messages high resolution controllers send into 14-bit values.
+// NRPN and RPN are always decoded. CC pairs are opt in, since plenty of
+// devices (the Twister included) use CC 32-63 as controls of their own.
+//
+// Following the MIDI spec an MSB on its own clears the LSB, so a pair gives a
+// coarse value followed by the fine one and 7-bit senders still work.
+#[derive(Clone, Debug, Default)]
+pub struct HighResDecoder {
+    // One bit per MSB control, per channel.
+    pairs: [u32; 16],
+    channels: [ChannelState; 16],
+}
+
+impl HighResDecoder {
+    pub fn new() -> Self {
+        HighResDecoder::default()
+    }
+
+    // Treats `msb` (0-31) and `msb + 32` on `channel` as one 14-bit control.
+    pub fn pair(mut self, channel: Channel, msb: ControlFunction) -> Self {
+        let cc = u8::from(msb);
+        assert!(cc < 32, "CC {} has no LSB pair", cc);
+        self.pairs[channel.index() as usize] |= 1 << cc;
+        self
+    }
+
+    pub fn all_pairs(mut self, channel: Channel) -> Self {
+        self.pairs[channel.index() as usize] = u32::MAX;
+        self
+    }
+
+    pub fn feed_event(&mut self, event: &MidiEvent) -> Option<HighResEvent> {
+        let (channel, param, value) = self.feed(&event.message)?;
+        Some(HighResEvent {
+            timestamp_us: event.timestamp_us,
+            port: event.port.clone(),
+            channel,
+            param,
+            value,
+        })
+    }
+
+    pub fn feed(&mut self, message: &MidiMessage) -> Option<(Channel, Param, U14)> {
+        let (channel, cc, value) = match message {
+            MidiMessage::ControlChange(channel, control, value) => {
+                (*channel, u8::from(*control), u8::from(*value))
+            }
+            _ => return None,
+        };
+        let pairs = self.pairs[channel.index() as usize];
+        let state = &mut self.channels[channel.index() as usize];
+        let is_pair = |msb: u8| pairs & (1 << msb) != 0;
+
+        match cc {
+            NRPN_MSB => state.selecting = select(state.selecting, Some(value), None, false),
+            NRPN_LSB => state.selecting = select(state.selecting, None, Some(value), false),
+            RPN_MSB => state.selecting = select(state.selecting, Some(value), None, true),
+            RPN_LSB => state.selecting = select(state.selecting, None, Some(value), true),
+            DATA_ENTRY_MSB | DATA_ENTRY_LSB | DATA_INCREMENT | DATA_DECREMENT
+                if selected(state.selecting).is_some() =>
+            {
+                state.data = match cc {
+                    DATA_ENTRY_MSB => (value as u16) << 7,
+                    DATA_ENTRY_LSB => (state.data & !0x7F) | value as u16,
+                    DATA_INCREMENT => (state.data + 1).min(u16::from(U14::MAX)),
+                    _ => state.data.saturating_sub(1),
+                };
+                let param = selected(state.selecting)?;
+                return Some((channel, param, u14(state.data)));
+            }
+            msb if msb < 32 && is_pair(msb) => {
+                state.cc_msb[msb as usize] = value;
+                let value = (value as u16) << 7;
+                return Some((channel, cc_param(msb), u14(value)));
+            }
+            lsb if (32..64).contains(&lsb) && is_pair(lsb - 32) => {
+                let msb = lsb - 32;
+                let value = (state.cc_msb[msb as usize] as u16) << 7 | value as u16;
+                return Some((channel, cc_param(msb), u14(value)));
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+// Values are built from two 7-bit halves, so they always fit.
+fn u14(value: u16) -> U14 {
+    U14::try_from(value).unwrap_or(U14::MAX)
+}
+
+fn cc_param(msb: u8) -> Param {
+    Param::Cc(ControlFunction(U7::from_u8_lossy(msb)))
+}
+
+// Parameter numbers are built from two CCs that may arrive in either order.
+// Switching between NRPN and RPN starts a new number.
+fn select(current: Selecting, msb: Option<u8>, lsb: Option<u8>, rpn: bool) -> Selecting {
+    let (old_msb, old_lsb) = match (current, rpn) {
+        (Selecting::Nrpn(m, l), false) | (Selecting::Rpn(m, l), true) => (m, l),
+        _ => (0, 0),
+    };
+    let (msb, lsb) = (msb.unwrap_or(old_msb), lsb.unwrap_or(old_lsb));
+    if rpn {
+        Selecting::Rpn(msb, lsb)
+    } else {
+        Selecting::Nrpn(msb, lsb)
+    }
+}
+
+fn selected(selecting: Selecting) -> Option<Param> {
+    match selecting {
+        Selecting::None => None,
+        Selecting::Nrpn(msb, lsb) => Some(Param::Nrpn((msb as u16) << 7 | lsb as u16)),
+        Selecting::Rpn(msb, lsb) => {
+            let number = (msb as u16) << 7 | lsb as u16;
+            (number != RPN_NULL).then_some(Param::Rpn(number))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cc(control: u8, value: u8) -> MidiMessage<'static> {
+        MidiMessage::ControlChange(
+            Channel::Ch1,
+            ControlFunction(U7::from_u8_lossy(control)),
+            U7::from_u8_lossy(value),
+        )
+    }
+
+    fn feed_all(decoder: &mut HighResDecoder, messages: &[(u8, u8)]) -> Vec<(Param, u16)> {
+        messages
+            .iter()
+            .filter_map(|(control, value)| decoder.feed(&cc(*control, *value)))
+            .map(|(_, param, value)| (param, u16::from(value)))
+            .collect()
+    }
+
+    #[test]
+    fn nrpn_data_entry() {
+        let mut decoder = HighResDecoder::new();
+        let decoded = feed_all(
+            &mut decoder,
+            &[(NRPN_MSB, 1), (NRPN_LSB, 2), (DATA_ENTRY_MSB, 3), (DATA_ENTRY_LSB, 4)],
+        );
+        let param = Param::Nrpn(1 << 7 | 2);
+        assert_eq!(decoded, vec![(param, 3 << 7), (param, 3 << 7 | 4)]);
+    }
+
+    #[test]
+    fn data_increment_and_decrement() {
+        let mut decoder = HighResDecoder::new();
+        let decoded = feed_all(
+            &mut decoder,
+            &[
+                (RPN_MSB, 0),
+                (RPN_LSB, 0),
+                (DATA_ENTRY_MSB, 1),
+                (DATA_INCREMENT, 0),
+                (DATA_DECREMENT, 0),
+            ],
+        );
+        let param = Param::Rpn(0);
+        assert_eq!(decoded, vec![(param, 128), (param, 129), (param, 128)]);
+    }
+
+    #[test]
+    fn rpn_null_deselects() {
+        let mut decoder = HighResDecoder::new();
+        let decoded = feed_all(
+            &mut decoder,
+            &[(RPN_MSB, 0), (RPN_LSB, 0), (RPN_MSB, 127), (RPN_LSB, 127), (DATA_ENTRY_MSB, 5)],
+        );
+        assert!(decoded.is_empty());
+    }
+
+    #[test]
+    fn cc_pairs_are_opt_in() {
+        let mut decoder = HighResDecoder::new();
+        assert!(feed_all(&mut decoder, &[(1, 10), (33, 20)]).is_empty());
+
+        let msb = ControlFunction(U7::from_u8_lossy(1));
+        let mut decoder = HighResDecoder::new().pair(Channel::Ch1, msb);
+        let param = cc_param(1);
+        assert_eq!(
+            feed_all(&mut decoder, &[(1, 10), (33, 20)]),
+            vec![(param, 10 << 7), (param, 10 << 7 | 20)]
+        );
+    }
+}
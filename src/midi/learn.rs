@@ -0,0 +1,232 @@
+use nannou::prelude::Key;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use wmidi::{Channel, ControlFunction, MidiMessage, U7};
+
+use super::error::Error;
+
+type Control = (Channel, ControlFunction);
+
+#[derive(Serialize, Deserialize)]
+struct BindingsFile {
+    bindings: BTreeMap<String, BindingEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BindingEntry {
+    channel: u8,
+    cc: u8,
+}
+
+// Binds named parameters to control changes at runtime. Select a parameter,
+// start learning and the next control that moves is bound to it. Bindings
+// are written to the file given to `load`, so they stick between runs.
+//
+// With no parameters there is nothing to select or learn, and those methods
+// do nothing.
+//
+// Keys, see `key_pressed`:
+//   [ / ]    select the previous / next parameter
+//   L        learn the selected parameter, or cancel
+#[derive(Clone, Debug)]
+pub struct Learn {
+    names: Vec<String>,
+    bindings: Vec<Option<Control>>,
+    selected: usize,
+    learning: bool,
+    path: Option<PathBuf>,
+}
+
+impl Learn {
+    pub fn new(names: &[&str]) -> Self {
+        Learn {
+            names: names.iter().map(|n| n.to_string()).collect(),
+            bindings: vec![None; names.len()],
+            selected: 0,
+            learning: false,
+            path: None,
+        }
+    }
+
+    // A default, used until something else is learned or loaded.
+    pub fn bind(mut self, name: &str, channel: Channel, control: ControlFunction) -> Self {
+        if let Some(i) = self.position(name) {
+            self.assign(i, (channel, control));
+        }
+        self
+    }
+
+    // Reads the bindings saved at `path` over the defaults and saves there
+    // from now on. A missing file just keeps the defaults.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        self.path = Some(path.to_owned());
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let file: BindingsFile = toml::from_str(&text).map_err(Error::Bindings)?;
+        for (name, entry) in file.bindings {
+            let channel = entry.channel.checked_sub(1).map(Channel::from_index);
+            // Parameters a sketch no longer has are dropped on the next save.
+            if let (Some(i), Some(Ok(channel)), true) = (self.position(&name), channel, entry.cc < 128) {
+                self.assign(i, (channel, ControlFunction(U7::from_u8_lossy(entry.cc))));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let bindings = self
+            .names
+            .iter()
+            .zip(&self.bindings)
+            .filter_map(|(name, binding)| {
+                let (channel, control) = (*binding)?;
+                let entry = BindingEntry {
+                    channel: channel.number(),
+                    cc: u8::from(control.0),
+                };
+                Some((name.clone(), entry))
+            })
+            .collect();
+        let text = toml::to_string(&BindingsFile { bindings }).expect("bindings are plain tables");
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn selected(&self) -> Option<&str> {
+        self.names.get(self.selected).map(String::as_str)
+    }
+
+    pub fn select(&mut self, name: &str) {
+        if let Some(i) = self.position(name) {
+            self.selected = i;
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.names.is_empty() {
+            self.selected = (self.selected + 1) % self.names.len();
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        if !self.names.is_empty() {
+            self.selected = (self.selected + self.names.len() - 1) % self.names.len();
+        }
+    }
+
+    pub fn is_learning(&self) -> bool {
+        self.learning
+    }
+
+    pub fn start(&mut self) {
+        self.learning = !self.names.is_empty();
+    }
+
+    pub fn cancel(&mut self) {
+        self.learning = false;
+    }
+
+    pub fn binding(&self, name: &str) -> Option<Control> {
+        self.bindings[self.position(name)?]
+    }
+
+    // Returns the parameter the message is bound to and its value. While
+    // learning the first control change is bound to the selected parameter
+    // first, and the bindings are saved.
+    pub fn feed(&mut self, message: &MidiMessage) -> Option<(&str, U7)> {
+        let (channel, control, value) = match message {
+            MidiMessage::ControlChange(channel, control, value) => (*channel, *control, *value),
+            _ => return None,
+        };
+        if self.learning {
+            self.learning = false;
+            self.assign(self.selected, (channel, control));
+            if let Err(e) = self.save() {
+                eprintln!("Could not save midi bindings: {}", e);
+            }
+        }
+        let i = self.bindings.iter().position(|b| *b == Some((channel, control)))?;
+        Some((&self.names[i], value))
+    }
+
+    // Returns whether the key was one of the learn keys.
+    pub fn key_pressed(&mut self, key: Key) -> bool {
+        match key {
+            Key::LBracket => self.select_previous(),
+            Key::RBracket => self.select_next(),
+            Key::L if self.learning => self.cancel(),
+            Key::L => self.start(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    // A control drives one parameter, so it is taken off any other first.
+    fn assign(&mut self, i: usize, control: Control) {
+        for binding in &mut self.bindings {
+            if *binding == Some(control) {
+                *binding = None;
+            }
+        }
+        self.bindings[i] = Some(control);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cc(channel: Channel, cc: u8, value: u8) -> MidiMessage<'static> {
+        MidiMessage::ControlChange(
+            channel,
+            ControlFunction(U7::from_u8_lossy(cc)),
+            U7::from_u8_lossy(value),
+        )
+    }
+
+    #[test]
+    fn no_parameters_does_nothing() {
+        let mut learn = Learn::new(&[]);
+        assert_eq!(learn.selected(), None);
+        assert!(learn.key_pressed(Key::RBracket));
+        assert!(learn.key_pressed(Key::LBracket));
+        assert!(learn.key_pressed(Key::L));
+        assert!(!learn.is_learning());
+        assert_eq!(learn.feed(&cc(Channel::Ch1, 7, 1)), None);
+    }
+
+    #[test]
+    fn selection_wraps() {
+        let mut learn = Learn::new(&["a", "b"]);
+        learn.select_previous();
+        assert_eq!(learn.selected(), Some("b"));
+        learn.select_next();
+        assert_eq!(learn.selected(), Some("a"));
+    }
+
+    #[test]
+    fn learning_moves_a_control() {
+        let mut learn = Learn::new(&["a", "b"]).bind("a", Channel::Ch1, ControlFunction(U7::MIN));
+        learn.select("b");
+        learn.start();
+        assert_eq!(learn.feed(&cc(Channel::Ch1, 0, 5)), Some(("b", U7::from_u8_lossy(5))));
+        assert!(!learn.is_learning());
+        assert_eq!(learn.binding("a"), None);
+    }
+}
@@ -1,38 +1,64 @@
-use midir::{Ignore, MidiInput, MidiInputConnection};
+use midir::{MidiInput, MidiInputConnection};
 use std::sync::mpsc::Sender;
 
+pub mod clock;
+pub mod coalesce;
+pub mod config;
+pub mod error;
+pub mod event;
+pub mod high_res;
+pub mod learn;
+pub mod notes;
+pub mod output;
+pub mod params;
+pub mod playback;
+pub mod profile;
+pub mod queue;
+pub mod record;
+pub mod source;
+pub mod supervisor;
 pub mod twister;
 
-pub fn init(tx: Sender<Vec<u8>>) -> Option<MidiInputConnection<()>> { 
-    let mut midi_in = match MidiInput::new("reading input") {
-        Err(_) => panic!(),
-        Ok(midi_input) => midi_input,
-    };
-    midi_in.ignore(Ignore::None);
-    let in_port = midi_in
-        .ports()
-        .iter()
-        .find(|&p| midi_in.port_name(p).unwrap().contains("Fighter"))
-        .cloned();
-    
-    match in_port {
-        None => {
-            println!("No midi port :(");
-            None
-        }
-        Some(input_port) => {
-            match midi_in.connect(
-                &input_port,
-                "midir-read-input",
-                move|_stamp, message, _| {
-                    tx.send(Vec::from(message)).unwrap();
-                },
-                (),
-            ) {
-                Err(_) => None,
-                Ok(connection) => Some(connection),
-            }
-        }
-    }
+pub use clock::{Clock, Transport};
+pub use coalesce::{coalesce, drain_coalesced};
+pub use config::{MidiConfig, PortSelector};
+pub use error::Error;
+pub use event::{Diagnostic, Event, MidiEvent};
+pub use high_res::{HighResDecoder, HighResEvent, Param};
+pub use learn::Learn;
+pub use notes::{Adsr, NoteState, NoteTracker};
+pub use output::Output;
+pub use params::{ParamInput, ParamStore};
+pub use playback::Playback;
+pub use profile::{ControlId, ControllerState, Profile};
+pub use queue::{EventQueue, Overflow, QueuedInput};
+pub use record::Recorder;
+pub use source::{MidiSource, MockSource};
+pub use supervisor::Supervisor;
+
+pub fn init(tx: Sender<Event>) -> Result<MidiInputConnection<()>, Error> {
+    MidiConfig::default().start(tx)
+}
+
+// Opens every config and merges their events into `tx`. Events carry the
+// config's label, or the port name if it has none.
+pub fn init_all(
+    configs: &[MidiConfig],
+    tx: Sender<Event>,
+) -> Vec<Result<MidiInputConnection<()>, Error>> {
+    configs.iter().map(|c| c.connect(tx.clone())).collect()
+}
+
+// Like `init`, but through a fixed size queue that drops the oldest
+// messages if the sketch falls behind.
+pub fn init_queue() -> Result<QueuedInput, Error> {
+    MidiConfig::default().connect_queue(queue::DEFAULT_CAPACITY, Overflow::DropOldest)
+}
+
+pub fn supervise(tx: Sender<Event>) -> Supervisor {
+    Supervisor::start(MidiConfig::default(), tx)
 }
 
+pub fn list_ports() -> Result<Vec<String>, Error> {
+    config::port_names(&MidiInput::new("listing ports")?)
+}
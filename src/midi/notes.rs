@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use wmidi::{Channel, MidiMessage, Note, U7};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Adsr {
+    pub attack: Duration,
+    pub decay: Duration,
+    // Level held after the decay, 0..1.
+    pub sustain: f32,
+    pub release: Duration,
+}
+
+impl Adsr {
+    pub const fn new(attack: Duration, decay: Duration, sustain: f32, release: Duration) -> Self {
+        Adsr {
+            attack,
+            decay,
+            sustain,
+            release,
+        }
+    }
+
+    fn held(&self, t: Duration) -> f32 {
+        if t < self.attack {
+            t.as_secs_f32() / self.attack.as_secs_f32()
+        } else if t < self.attack + self.decay {
+            let into_decay = (t - self.attack).as_secs_f32() / self.decay.as_secs_f32();
+            1.0 - (1.0 - self.sustain) * into_decay
+        } else {
+            self.sustain
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NoteState {
+    pub channel: Channel,
+    pub note: Note,
+    pub velocity: U7,
+    pub on: Instant,
+    // `None` while the note is held.
+    pub released: Option<Instant>,
+    // Polyphonic aftertouch, 0 if the controller doesn't send it.
+    pub pressure: U7,
+}
+
+impl NoteState {
+    pub fn is_held(&self) -> bool {
+        self.released.is_none()
+    }
+
+    // How long it has been held, or was held for if released.
+    pub fn duration(&self) -> Duration {
+        self.released.unwrap_or_else(Instant::now) - self.on
+    }
+
+    // 0..1, not scaled by velocity.
+    pub fn envelope(&self, adsr: &Adsr, now: Instant) -> f32 {
+        match self.released {
+            None => adsr.held(now.saturating_duration_since(self.on)),
+            Some(released) => {
+                let from = adsr.held(released - self.on);
+                let t = now.saturating_duration_since(released);
+                if t >= adsr.release {
+                    0.0
+                } else {
+                    from * (1.0 - t.as_secs_f32() / adsr.release.as_secs_f32())
+                }
+            }
+        }
+    }
+}
+
+// Every note that is held on every channel, plus the ones released in the
+// last `keep`, so visuals can fade them out.
+#[derive(Clone, Debug)]
+pub struct NoteTracker {
+    notes: BTreeMap<(u8, u8), NoteState>,
+    channel_pressure: [U7; 16],
+    keep: Duration,
+}
+
+impl Default for NoteTracker {
+    fn default() -> Self {
+        NoteTracker {
+            notes: BTreeMap::new(),
+            channel_pressure: [U7::MIN; 16],
+            keep: Duration::from_secs(10),
+        }
+    }
+}
+
+impl NoteTracker {
+    pub fn new() -> Self {
+        NoteTracker::default()
+    }
+
+    // How long released notes are remembered. Should cover the longest
+    // release time used with `envelope`.
+    pub fn keep(mut self, keep: Duration) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    // Returns whether the message was about notes.
+    pub fn feed(&mut self, message: &MidiMessage) -> bool {
+        self.feed_at(message, Instant::now())
+    }
+
+    pub fn feed_at(&mut self, message: &MidiMessage, now: Instant) -> bool {
+        let keep = self.keep;
+        self.notes
+            .retain(|_, n| n.released.is_none_or(|r| now.saturating_duration_since(r) < keep));
+
+        match *message {
+            // Plenty of controllers send note on with velocity 0 for note off.
+            MidiMessage::NoteOn(channel, note, velocity) if u8::from(velocity) > 0 => {
+                self.notes.insert(
+                    key(channel, note),
+                    NoteState {
+                        channel,
+                        note,
+                        velocity,
+                        on: now,
+                        released: None,
+                        pressure: U7::MIN,
+                    },
+                );
+            }
+            MidiMessage::NoteOn(channel, note, _) | MidiMessage::NoteOff(channel, note, _) => {
+                if let Some(state) = self.notes.get_mut(&key(channel, note)) {
+                    state.released.get_or_insert(now);
+                }
+            }
+            MidiMessage::PolyphonicKeyPressure(channel, note, pressure) => {
+                if let Some(state) = self.notes.get_mut(&key(channel, note)) {
+                    state.pressure = pressure;
+                }
+            }
+            MidiMessage::ChannelPressure(channel, pressure) => {
+                self.channel_pressure[channel.index() as usize] = pressure;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Held notes, by channel then pitch.
+    pub fn held(&self) -> impl Iterator<Item = &NoteState> {
+        self.notes.values().filter(|n| n.is_held())
+    }
+
+    pub fn held_on(&self, channel: Channel) -> impl Iterator<Item = &NoteState> {
+        self.held().filter(move |n| n.channel == channel)
+    }
+
+    pub fn is_held(&self, note: Note) -> bool {
+        self.held().any(|n| n.note == note)
+    }
+
+    pub fn recently_released(&self, within: Duration) -> impl Iterator<Item = &NoteState> {
+        let now = Instant::now();
+        self.notes
+            .values()
+            .filter(move |n| n.released.is_some_and(|r| now.saturating_duration_since(r) <= within))
+    }
+
+    // Held and remembered notes, for drawing envelopes.
+    pub fn all(&self) -> impl Iterator<Item = &NoteState> {
+        self.notes.values()
+    }
+
+    // The loudest envelope of `note` across channels, 0..1.
+    pub fn envelope(&self, note: Note, adsr: &Adsr) -> f32 {
+        let now = Instant::now();
+        self.notes
+            .values()
+            .filter(|n| n.note == note)
+            .map(|n| n.envelope(adsr, now))
+            .fold(0.0, f32::max)
+    }
+
+    // Polyphonic aftertouch of a held note, or its channel's pressure if
+    // that's higher.
+    pub fn pressure(&self, channel: Channel, note: Note) -> U7 {
+        let channel_pressure = self.channel_pressure[channel.index() as usize];
+        match self.notes.get(&key(channel, note)) {
+            Some(state) if state.is_held() => state.pressure.max(channel_pressure),
+            _ => U7::MIN,
+        }
+    }
+}
+
+fn key(channel: Channel, note: Note) -> (u8, u8) {
+    (channel.index(), u8::from(note))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADSR: Adsr = Adsr::new(
+        Duration::from_millis(10),
+        Duration::from_millis(100),
+        0.5,
+        Duration::from_millis(200),
+    );
+
+    fn on(velocity: u8) -> MidiMessage<'static> {
+        MidiMessage::NoteOn(Channel::Ch1, Note::C4, U7::from_u8_lossy(velocity))
+    }
+
+    #[test]
+    fn note_on_with_velocity_0_releases() {
+        let mut notes = NoteTracker::new();
+        let start = Instant::now();
+        notes.feed_at(&on(100), start);
+        assert!(notes.is_held(Note::C4));
+
+        let released = start + Duration::from_millis(50);
+        assert!(notes.feed_at(&on(0), released));
+        assert!(!notes.is_held(Note::C4));
+        let state = notes.all().next().unwrap();
+        assert_eq!(state.released, Some(released));
+        assert_eq!(state.velocity, U7::from_u8_lossy(100));
+    }
+
+    #[test]
+    fn envelope_fades_out_over_the_release() {
+        let mut notes = NoteTracker::new();
+        let start = Instant::now();
+        notes.feed_at(&on(100), start);
+        // Released well into the sustain.
+        let released = start + Duration::from_millis(500);
+        notes.feed_at(&MidiMessage::NoteOff(Channel::Ch1, Note::C4, U7::MIN), released);
+        let state = notes.all().next().unwrap();
+
+        assert_eq!(state.envelope(&ADSR, released), 0.5);
+        let halfway = state.envelope(&ADSR, released + Duration::from_millis(100));
+        assert!((halfway - 0.25).abs() < 1e-6, "{}", halfway);
+        assert!(state.envelope(&ADSR, released + Duration::from_millis(199)) > 0.0);
+        assert_eq!(state.envelope(&ADSR, released + ADSR.release), 0.0);
+    }
+
+    #[test]
+    fn released_notes_are_forgotten_after_keep() {
+        let mut notes = NoteTracker::new().keep(Duration::from_secs(1));
+        let start = Instant::now();
+        notes.feed_at(&on(100), start);
+        notes.feed_at(&on(0), start);
+        notes.feed_at(&MidiMessage::TimingClock, start + Duration::from_secs(2));
+        assert_eq!(notes.all().count(), 0);
+    }
+}
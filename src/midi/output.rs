@@ -0,0 +1,85 @@
+use midir::{MidiOutput, MidiOutputConnection};
+use wmidi::{Channel, ControlFunction, MidiMessage, Note, U7};
+
+use super::config::{port_names, MidiConfig};
+use super::error::Error;
+
+pub struct Output {
+    connection: MidiOutputConnection,
+    port: String,
+}
+
+impl Output {
+    pub fn connect(config: &MidiConfig) -> Result<Output, Error> {
+        let midi_out = MidiOutput::new(&config.client_name)?;
+        let ports = midi_out.ports();
+        let names = port_names(&midi_out)?;
+
+        let i = config
+            .find_port(&names)
+            .ok_or_else(|| Error::PortNotFound {
+                available: names.clone(),
+            })?;
+        let connection = midi_out
+            .connect(&ports[i], "midir-write-output")
+            .map_err(|e| Error::Connect {
+                port: names[i].clone(),
+                kind: e.kind(),
+            })?;
+        Ok(Output {
+            connection,
+            port: names[i].clone(),
+        })
+    }
+
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+
+    pub fn send(&mut self, message: &MidiMessage) -> Result<(), Error> {
+        let mut bytes = vec![0u8; message.bytes_size()];
+        message
+            .copy_to_slice(&mut bytes)
+            .expect("buffer is sized from the message");
+        self.connection.send(&bytes).map_err(Error::Send)
+    }
+
+    pub fn control_change(
+        &mut self,
+        channel: Channel,
+        control: ControlFunction,
+        value: U7,
+    ) -> Result<(), Error> {
+        self.send(&MidiMessage::ControlChange(channel, control, value))
+    }
+
+    pub fn note_on(&mut self, channel: Channel, note: Note, velocity: U7) -> Result<(), Error> {
+        self.send(&MidiMessage::NoteOn(channel, note, velocity))
+    }
+
+    pub fn note_off(&mut self, channel: Channel, note: Note, velocity: U7) -> Result<(), Error> {
+        self.send(&MidiMessage::NoteOff(channel, note, velocity))
+    }
+
+    // `data` is everything between the 0xF0 and 0xF7 bytes.
+    pub fn sysex(&mut self, data: &[U7]) -> Result<(), Error> {
+        self.send(&MidiMessage::SysEx(data))
+    }
+
+    // Sends each normalised 0..1 value as a control change, so the
+    // controller's rings or faders match the sketch's current parameters.
+    pub fn send_values(
+        &mut self,
+        channel: Channel,
+        values: &[(ControlFunction, f32)],
+    ) -> Result<(), Error> {
+        for &(control, value) in values {
+            self.control_change(channel, control, to_u7(value))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn to_u7(value: f32) -> U7 {
+    U7::from_u8_lossy((value.clamp(0.0, 1.0) * 127.0).round() as u8)
+}
@@ -0,0 +1,168 @@
+use midir::MidiInputConnection;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+use wmidi::{Channel, ControlFunction, MidiMessage, U7};
+
+use crate::util;
+
+const CONTROL_CHANGE: u8 = 0xB0;
+
+// The latest value of every control change on every channel, as atomics the
+// midir thread writes straight into, see `MidiConfig::connect_params`. The
+// sketch reads whenever it likes without locks or a channel to drain, at the
+// cost of only ever seeing the current value: use a receiver for notes or
+// anything else where each message matters.
+pub struct ParamStore {
+    values: Box<[AtomicU8]>,
+    writes: AtomicU64,
+}
+
+// The values themselves would be 2048 lines.
+impl std::fmt::Debug for ParamStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ParamStore").field("writes", &self.writes()).finish()
+    }
+}
+
+impl Default for ParamStore {
+    fn default() -> Self {
+        ParamStore {
+            values: (0..16 * 128).map(|_| AtomicU8::new(0)).collect(),
+            writes: AtomicU64::new(0),
+        }
+    }
+}
+
+impl ParamStore {
+    pub fn new() -> Self {
+        ParamStore::default()
+    }
+
+    // A starting value, until the controller sends one.
+    pub fn set(&self, channel: Channel, control: ControlFunction, value: U7) {
+        self.store(channel.index(), u8::from(control), u8::from(value));
+    }
+
+    pub fn get(&self, channel: Channel, control: ControlFunction) -> U7 {
+        let slot = slot(channel.index(), u8::from(control));
+        U7::from_u8_lossy(self.values[slot].load(Ordering::Relaxed))
+    }
+
+    pub fn unipolar(&self, channel: Channel, control: ControlFunction) -> f32 {
+        util::unipolar(self.get(channel, control))
+    }
+
+    // Goes up with every value written, so a sketch can skip work when
+    // nothing moved since the last frame.
+    pub fn writes(&self) -> u64 {
+        self.writes.load(Ordering::Acquire)
+    }
+
+    // For sources that produce messages rather than bytes, e.g. `MockSource`
+    // or `Playback`. Returns whether it was a control change.
+    pub fn feed(&self, message: &MidiMessage) -> bool {
+        match *message {
+            MidiMessage::ControlChange(channel, control, value) => {
+                self.set(channel, control, value);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Called from the midir callback, so it never allocates or blocks.
+    pub fn write(&self, bytes: &[u8]) -> bool {
+        match *bytes {
+            [status, control, value]
+                if status & 0xF0 == CONTROL_CHANGE && control < 128 && value < 128 =>
+            {
+                self.store(status & 0x0F, control, value);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn store(&self, channel: u8, control: u8, value: u8) {
+        self.values[slot(channel, control)].store(value, Ordering::Relaxed);
+        self.writes.fetch_add(1, Ordering::Release);
+    }
+}
+
+fn slot(channel: u8, control: u8) -> usize {
+    channel as usize * 128 + control as usize
+}
+
+// Keeps the connection writing into a `ParamStore` open.
+pub struct ParamInput {
+    _connection: MidiInputConnection<()>,
+    store: Arc<ParamStore>,
+    port: Arc<str>,
+}
+
+impl ParamInput {
+    pub(crate) fn new(
+        connection: MidiInputConnection<()>,
+        store: Arc<ParamStore>,
+        port: Arc<str>,
+    ) -> Self {
+        ParamInput {
+            _connection: connection,
+            store,
+            port,
+        }
+    }
+
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+
+    pub fn store(&self) -> &Arc<ParamStore> {
+        &self.store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control(cc: u8) -> ControlFunction {
+        ControlFunction(U7::from_u8_lossy(cc))
+    }
+
+    #[test]
+    fn write_takes_the_channel_from_the_status() {
+        let store = ParamStore::new();
+        assert!(store.write(&[0xB5, 7, 100]));
+        assert_eq!(store.get(Channel::Ch6, control(7)), U7::from_u8_lossy(100));
+        assert_eq!(store.get(Channel::Ch1, control(7)), U7::MIN);
+        assert_eq!(store.writes(), 1);
+    }
+
+    #[test]
+    fn write_rejects_everything_else() {
+        let store = ParamStore::new();
+        assert!(!store.write(&[0x95, 7, 100]));
+        assert!(!store.write(&[0xE0, 7, 100]));
+        assert!(!store.write(&[0xB0, 7]));
+        assert!(!store.write(&[0xB0, 128, 1]));
+        assert!(!store.write(&[0xB0, 7, 128]));
+        assert!(!store.write(&[0xF0, 0xB0, 7, 1, 0xF7]));
+        assert_eq!(store.writes(), 0);
+        assert_eq!(store.get(Channel::Ch1, control(7)), U7::MIN);
+    }
+
+    #[test]
+    fn feed_and_set() {
+        let store = ParamStore::new();
+        store.set(Channel::Ch16, control(127), U7::MAX);
+        assert_eq!(store.unipolar(Channel::Ch16, control(127)), 1.0);
+
+        let note = MidiMessage::NoteOn(Channel::Ch1, wmidi::Note::C4, U7::MAX);
+        assert!(!store.feed(&note));
+        let cc = MidiMessage::ControlChange(Channel::Ch1, control(1), U7::from_u8_lossy(64));
+        assert!(store.feed(&cc));
+        assert_eq!(store.get(Channel::Ch1, control(1)), U7::from_u8_lossy(64));
+        assert_eq!(store.writes(), 2);
+    }
+}
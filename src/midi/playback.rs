@@ -0,0 +1,171 @@
+use midly::{MetaMessage, Smf, Timing, TrackEventKind};
+use std::path::Path;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use wmidi::MidiMessage;
+
+use super::error::Error;
+use super::event::{Event, MidiEvent};
+use super::record::MICROS_PER_BEAT;
+
+pub const PLAYBACK_ENV_VAR: &str = "SKETCHES_MIDI_PLAYBACK";
+
+// Replays a Standard MIDI File into the same channel `midi::init` feeds.
+// Call `advance` from `update` to follow the sketch's frame clock, or `spawn`
+// it to play back in real time on its own thread.
+pub struct Playback {
+    events: Vec<MidiEvent>,
+    next: usize,
+    position: Duration,
+    tx: Sender<Event>,
+}
+
+impl Playback {
+    pub fn open<P: AsRef<Path>>(path: P, tx: Sender<Event>) -> Result<Playback, Error> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)?;
+        let port: Arc<str> = Arc::from(
+            path.file_stem()
+                .map_or("playback".into(), |s| s.to_string_lossy()),
+        );
+        Ok(Playback {
+            events: decode(&data, &port)?,
+            next: 0,
+            position: Duration::ZERO,
+            tx,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.events.len()
+    }
+
+    pub fn position(&self) -> Duration {
+        self.position
+    }
+
+    pub fn rewind(&mut self) {
+        self.next = 0;
+        self.position = Duration::ZERO;
+    }
+
+    // Moves the playhead forward and sends every event it passed.
+    pub fn advance(&mut self, elapsed: Duration) {
+        self.position += elapsed;
+        let until = self.position.as_micros() as u64;
+        while let Some(event) = self.events.get(self.next) {
+            if event.timestamp_us > until {
+                break;
+            }
+            if self.tx.send(Event::Midi(event.clone())).is_err() {
+                self.next = self.events.len();
+                return;
+            }
+            self.next += 1;
+        }
+    }
+
+    pub fn spawn(mut self) -> Player {
+        let (stop_tx, stop_rx) = channel();
+        let handle = thread::spawn(move || {
+            let start = Instant::now() - self.position;
+            while let Some(event) = self.events.get(self.next) {
+                let due = start + Duration::from_micros(event.timestamp_us);
+                let wait = due.saturating_duration_since(Instant::now());
+                match stop_rx.recv_timeout(wait) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
+                self.advance(start.elapsed().saturating_sub(self.position));
+            }
+        });
+
+        Player {
+            stop: Some(stop_tx),
+            handle: Some(handle),
+        }
+    }
+}
+
+// A playback running on its own thread, stopped when dropped.
+pub struct Player {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Flattens every track into one list of events, with timestamps in
+// microseconds from the start of the file following the tempo map.
+fn decode(data: &[u8], port: &Arc<str>) -> Result<Vec<MidiEvent>, Error> {
+    let smf = Smf::parse(data).map_err(Error::Smf)?;
+
+    let mut timeline: Vec<(u64, TrackEventKind)> = Vec::new();
+    for track in &smf.tracks {
+        let mut tick = 0u64;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            timeline.push((tick, event.kind));
+        }
+    }
+    timeline.sort_by_key(|(tick, _)| *tick);
+
+    let mut micros_per_tick = match smf.header.timing {
+        Timing::Metrical(ppq) => MICROS_PER_BEAT as f64 / ppq.as_int() as f64,
+        Timing::Timecode(fps, sub) => 1_000_000.0 / (fps.as_f32() as f64 * sub as f64),
+    };
+
+    let mut events = Vec::new();
+    let mut last_tick = 0;
+    let mut micros = 0.0;
+    for (tick, kind) in timeline {
+        micros += (tick - last_tick) as f64 * micros_per_tick;
+        last_tick = tick;
+        match kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                if let Timing::Metrical(ppq) = smf.header.timing {
+                    micros_per_tick = tempo.as_int() as f64 / ppq.as_int() as f64;
+                }
+            }
+            // The recorder stores realtime and system common messages as raw
+            // escapes, which midly won't turn back into live events.
+            TrackEventKind::Escape(data) => push(&mut events, data, micros, port),
+            kind => {
+                let mut bytes = Vec::new();
+                if let Some(live) = kind.as_live_event() {
+                    if live.write_std(&mut bytes).is_ok() {
+                        push(&mut events, &bytes, micros, port);
+                    }
+                }
+            }
+        }
+    }
+    Ok(events)
+}
+
+fn push(events: &mut Vec<MidiEvent>, bytes: &[u8], micros: f64, port: &Arc<str>) {
+    if let Ok(message) = MidiMessage::try_from(bytes) {
+        events.push(MidiEvent {
+            timestamp_us: micros.round() as u64,
+            port: port.clone(),
+            message: message.to_owned(),
+        });
+    }
+}
@@ -0,0 +1,320 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Instant;
+use wmidi::{Channel, ControlFunction, MidiMessage, Note, U7};
+
+use super::config::MidiConfig;
+use super::error::Error;
+use crate::util;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Knob,
+    Fader,
+    Button,
+    Pad,
+}
+
+impl Kind {
+    // Buttons and pads are read as pressed or not, knobs and faders as a
+    // position in their range.
+    pub fn is_switch(self) -> bool {
+        matches!(self, Kind::Button | Kind::Pad)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Source {
+    Cc(ControlFunction),
+    // Velocity is the value, note off and velocity 0 both read as 0.
+    Note(Note),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Control {
+    pub name: String,
+    pub kind: Kind,
+    pub channel: Channel,
+    pub source: Source,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Control {
+    pub fn matches(&self, message: &MidiMessage) -> Option<U7> {
+        match (self.source, message) {
+            (Source::Cc(control), MidiMessage::ControlChange(channel, c, value))
+                if *channel == self.channel && *c == control =>
+            {
+                Some(*value)
+            }
+            (Source::Note(note), MidiMessage::NoteOn(channel, n, velocity))
+                if *channel == self.channel && *n == note =>
+            {
+                Some(*velocity)
+            }
+            (Source::Note(note), MidiMessage::NoteOff(channel, n, _))
+                if *channel == self.channel && *n == note =>
+            {
+                Some(U7::MIN)
+            }
+            _ => None,
+        }
+    }
+
+    // Maps a raw value onto min..max.
+    pub fn scale(&self, value: U7) -> f32 {
+        self.min + (self.max - self.min) * util::unipolar(value)
+    }
+}
+
+// Index of a control in its profile, looked up once by name so the per-frame
+// reads don't compare strings.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ControlId(usize);
+
+// A device layout read from a TOML file, see assets/profiles.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    pub name: String,
+    pub port: Regex,
+    controls: Vec<Control>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileFile {
+    name: String,
+    port: String,
+    // Used by controls that don't give their own.
+    #[serde(default = "default_channel")]
+    channel: u8,
+    controls: Vec<ControlEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ControlEntry {
+    name: String,
+    kind: Kind,
+    channel: Option<u8>,
+    cc: Option<u8>,
+    note: Option<u8>,
+    #[serde(default)]
+    min: f32,
+    #[serde(default = "default_max")]
+    max: f32,
+}
+
+fn default_channel() -> u8 {
+    1
+}
+
+fn default_max() -> f32 {
+    1.0
+}
+
+impl Profile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Profile, Error> {
+        Profile::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Profile, Error> {
+        let file: ProfileFile = toml::from_str(text).map_err(Error::Profile)?;
+        let invalid = |reason: String| Error::InvalidProfile {
+            profile: file.name.clone(),
+            reason,
+        };
+
+        let port = Regex::new(&file.port).map_err(|e| invalid(e.to_string()))?;
+        let mut controls: Vec<Control> = Vec::with_capacity(file.controls.len());
+        for entry in &file.controls {
+            if controls.iter().any(|c| c.name == entry.name) {
+                return Err(invalid(format!("{:?} is defined twice", entry.name)));
+            }
+            let number = entry.channel.unwrap_or(file.channel);
+            let channel = number
+                .checked_sub(1)
+                .and_then(|i| Channel::from_index(i).ok())
+                .ok_or_else(|| invalid(format!("{:?} has channel {}, not 1-16", entry.name, number)))?;
+            let source = match (entry.cc, entry.note) {
+                (Some(cc), None) if cc < 128 => Source::Cc(ControlFunction(U7::from_u8_lossy(cc))),
+                (None, Some(note)) if note < 128 => Source::Note(Note::from_u8_lossy(note)),
+                _ => {
+                    return Err(invalid(format!(
+                        "{:?} needs exactly one of cc or note, 0-127",
+                        entry.name
+                    )))
+                }
+            };
+            controls.push(Control {
+                name: entry.name.clone(),
+                kind: entry.kind,
+                channel,
+                source,
+                min: entry.min,
+                max: entry.max,
+            });
+        }
+
+        Ok(Profile {
+            name: file.name,
+            port,
+            controls,
+        })
+    }
+
+    pub fn controls(&self) -> &[Control] {
+        &self.controls
+    }
+
+    pub fn id(&self, name: &str) -> Option<ControlId> {
+        self.controls.iter().position(|c| c.name == name).map(ControlId)
+    }
+
+    pub fn control(&self, id: ControlId) -> &Control {
+        &self.controls[id.0]
+    }
+
+    pub fn find(&self, message: &MidiMessage) -> Option<(ControlId, U7)> {
+        self.controls
+            .iter()
+            .enumerate()
+            .find_map(|(i, c)| c.matches(message).map(|value| (ControlId(i), value)))
+    }
+
+    // Connects to the first port matching the profile's pattern.
+    pub fn config(&self) -> MidiConfig {
+        MidiConfig::new().regex(self.port.clone())
+    }
+}
+
+// Latest value of every control in a profile, the profile counterpart of
+// `twister::TwisterState`.
+#[derive(Clone, Debug)]
+pub struct ControllerState {
+    profile: Profile,
+    values: Vec<U7>,
+    changed: Vec<Option<Instant>>,
+}
+
+impl ControllerState {
+    pub fn new(profile: Profile) -> Self {
+        let len = profile.controls.len();
+        ControllerState {
+            profile,
+            values: vec![U7::MIN; len],
+            changed: vec![None; len],
+        }
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    // Returns the control the message moved, if it belongs to the profile.
+    pub fn feed(&mut self, message: &MidiMessage) -> Option<ControlId> {
+        let (id, value) = self.profile.find(message)?;
+        self.values[id.0] = value;
+        self.changed[id.0] = Some(Instant::now());
+        Some(id)
+    }
+
+    pub fn set(&mut self, id: ControlId, value: U7) {
+        self.values[id.0] = value;
+    }
+
+    pub fn raw(&self, id: ControlId) -> U7 {
+        self.values[id.0]
+    }
+
+    // The control's position in its min..max range.
+    pub fn value(&self, id: ControlId) -> f32 {
+        self.profile.control(id).scale(self.values[id.0])
+    }
+
+    pub fn pressed(&self, id: ControlId) -> bool {
+        u8::from(self.values[id.0]) > 0
+    }
+
+    pub fn changed(&self, id: ControlId) -> Option<Instant> {
+        self.changed[id.0]
+    }
+
+    pub fn get(&self, name: &str) -> Option<f32> {
+        self.profile.id(name).map(|id| self.value(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_controls(controls: &str) -> Result<Profile, Error> {
+        Profile::parse(&format!("name = \"test\"\nport = \"test\"\ncontrols = [{}]", controls))
+    }
+
+    fn is_invalid(result: Result<Profile, Error>) -> bool {
+        matches!(result, Err(Error::InvalidProfile { .. }))
+    }
+
+    #[test]
+    fn shipped_profiles_parse() {
+        for text in [
+            include_str!("../../assets/profiles/twister.toml"),
+            include_str!("../../assets/profiles/nanokontrol2.toml"),
+            include_str!("../../assets/profiles/midimix.toml"),
+        ] {
+            let profile = Profile::parse(text).unwrap();
+            assert!(!profile.controls().is_empty(), "{}", profile.name);
+        }
+    }
+
+    #[test]
+    fn channel_defaults_and_overrides() {
+        let profile = parse_controls(
+            r#"{ name = "a", kind = "knob", cc = 1 },
+               { name = "b", kind = "pad", channel = 10, note = 36 }"#,
+        )
+        .unwrap();
+        let a = profile.control(profile.id("a").unwrap());
+        let cc = ControlFunction(U7::from_u8_lossy(1));
+        assert_eq!((a.channel, a.source), (Channel::Ch1, Source::Cc(cc)));
+        let b = profile.control(profile.id("b").unwrap());
+        let note = Note::from_u8_lossy(36);
+        assert_eq!((b.channel, b.source), (Channel::Ch10, Source::Note(note)));
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        assert!(is_invalid(parse_controls(
+            r#"{ name = "a", kind = "knob", cc = 1 }, { name = "a", kind = "knob", cc = 2 }"#
+        )));
+    }
+
+    #[test]
+    fn channel_must_be_1_to_16() {
+        let on = |channel: u8| {
+            parse_controls(&format!(r#"{{ name = "a", kind = "knob", channel = {}, cc = 1 }}"#, channel))
+        };
+        assert!(is_invalid(on(0)));
+        assert!(is_invalid(on(17)));
+        assert!(on(1).is_ok());
+        assert!(on(16).is_ok());
+    }
+
+    #[test]
+    fn exactly_one_of_cc_or_note() {
+        assert!(is_invalid(parse_controls(r#"{ name = "a", kind = "knob", cc = 1, note = 60 }"#)));
+        assert!(is_invalid(parse_controls(r#"{ name = "a", kind = "knob" }"#)));
+        assert!(is_invalid(parse_controls(r#"{ name = "a", kind = "knob", cc = 128 }"#)));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let result = parse_controls(r#"{ name = "a", kind = "knob", cc = 1, colour = "red" }"#);
+        assert!(matches!(result, Err(Error::Profile(_))));
+    }
+}
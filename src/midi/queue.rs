@@ -0,0 +1,239 @@
+use crossbeam_queue::ArrayQueue;
+use midir::MidiInputConnection;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use wmidi::MidiMessage;
+
+use super::event::MidiEvent;
+
+pub const DEFAULT_CAPACITY: usize = 256;
+// Everything but SysEx fits in three bytes.
+const MAX_LEN: usize = 3;
+const CONTROL_CHANGE: u8 = 0xB0;
+const PENDING: u64 = 0x80;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    // Make room by discarding the oldest queued message.
+    DropOldest,
+    // Hold on to the latest value of each control change until there's room
+    // again, so a knob never sticks at a stale value. Other messages drop
+    // the oldest. Held values are delivered after the queue, so they can
+    // overtake earlier messages.
+    Coalesce,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RawMessage {
+    pub timestamp_us: u64,
+    len: u8,
+    bytes: [u8; MAX_LEN],
+}
+
+impl RawMessage {
+    pub fn new(timestamp_us: u64, data: &[u8]) -> Option<RawMessage> {
+        if data.is_empty() || data.len() > MAX_LEN {
+            return None;
+        }
+        let mut bytes = [0; MAX_LEN];
+        bytes[..data.len()].copy_from_slice(data);
+        Some(RawMessage {
+            timestamp_us,
+            len: data.len() as u8,
+            bytes,
+        })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+// A bounded queue between the midir thread and the sketch that never
+// allocates or blocks on the midir side. Messages that don't fit, including
+// any SysEx, are counted in `dropped`.
+pub struct EventQueue {
+    ring: ArrayQueue<RawMessage>,
+    overflow: Overflow,
+    // Per channel and controller: timestamp << 8 | PENDING | value.
+    held: Box<[AtomicU64]>,
+    any_held: AtomicBool,
+    dropped: AtomicU64,
+}
+
+impl EventQueue {
+    pub fn new(capacity: usize, overflow: Overflow) -> Self {
+        EventQueue {
+            ring: ArrayQueue::new(capacity.max(1)),
+            overflow,
+            held: (0..16 * 128).map(|_| AtomicU64::new(0)).collect(),
+            any_held: AtomicBool::new(false),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+
+    // Messages lost to overflow since the queue was made.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn push(&self, timestamp_us: u64, bytes: &[u8]) {
+        let message = match RawMessage::new(timestamp_us, bytes) {
+            Some(message) => message,
+            None => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        let slot = match self.overflow {
+            Overflow::Coalesce => held_slot(bytes),
+            Overflow::DropOldest => None,
+        };
+        if let Some(slot) = slot {
+            if self.ring.is_full() {
+                let held = timestamp_us << 8 | PENDING | bytes[2] as u64;
+                if self.held[slot].swap(held, Ordering::AcqRel) & PENDING != 0 {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                self.any_held.store(true, Ordering::Release);
+                return;
+            }
+            // A newer value than the held one is about to be queued, which
+            // would otherwise be overwritten when the held one is delivered.
+            if self.held[slot].swap(0, Ordering::AcqRel) & PENDING != 0 {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if self.ring.force_push(message).is_some() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn pop(&self) -> Option<RawMessage> {
+        if let Some(message) = self.ring.pop() {
+            return Some(message);
+        }
+        if !self.any_held.swap(false, Ordering::AcqRel) {
+            return None;
+        }
+        for (slot, held) in self.held.iter().enumerate() {
+            let value = held.swap(0, Ordering::AcqRel);
+            if value & PENDING != 0 {
+                // There may be more, look again next time.
+                self.any_held.store(true, Ordering::Release);
+                let status = CONTROL_CHANGE | (slot / 128) as u8;
+                let bytes = [status, (slot % 128) as u8, (value & 0x7F) as u8];
+                return RawMessage::new(value >> 8, &bytes);
+            }
+        }
+        None
+    }
+}
+
+fn held_slot(bytes: &[u8]) -> Option<usize> {
+    match bytes {
+        [status, control, _] if status & 0xF0 == CONTROL_CHANGE => {
+            Some((status & 0x0F) as usize * 128 + *control as usize)
+        }
+        _ => None,
+    }
+}
+
+// An input that goes through an `EventQueue` instead of a channel. Keep it on
+// the model and read it with `try_iter` once per frame.
+pub struct QueuedInput {
+    _connection: MidiInputConnection<()>,
+    queue: Arc<EventQueue>,
+    port: Arc<str>,
+}
+
+impl QueuedInput {
+    pub(crate) fn new(
+        connection: MidiInputConnection<()>,
+        queue: Arc<EventQueue>,
+        port: Arc<str>,
+    ) -> Self {
+        QueuedInput {
+            _connection: connection,
+            queue,
+            port,
+        }
+    }
+
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped()
+    }
+
+    // Everything queued so far. Bytes that don't decode are skipped.
+    pub fn try_iter(&self) -> impl Iterator<Item = MidiEvent> + '_ {
+        std::iter::from_fn(move || self.queue.pop()).filter_map(move |raw| {
+            let message = MidiMessage::try_from(raw.bytes()).ok()?;
+            Some(MidiEvent {
+                timestamp_us: raw.timestamp_us,
+                port: self.port.clone(),
+                message: message.to_owned(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(queue: &EventQueue) -> Vec<Vec<u8>> {
+        std::iter::from_fn(|| queue.pop()).map(|m| m.bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_newest() {
+        let queue = EventQueue::new(2, Overflow::DropOldest);
+        for value in 0..5 {
+            queue.push(value as u64, &[0xB0, 7, value]);
+        }
+        assert_eq!(queue.dropped(), 3);
+        assert_eq!(drain(&queue), vec![vec![0xB0, 7, 3], vec![0xB0, 7, 4]]);
+    }
+
+    #[test]
+    fn coalesce_holds_control_changes_while_full() {
+        let queue = EventQueue::new(2, Overflow::Coalesce);
+        queue.push(1, &[0x90, 60, 100]);
+        queue.push(2, &[0x90, 61, 100]);
+        queue.push(3, &[0xB0, 7, 10]);
+        queue.push(4, &[0xB0, 7, 20]);
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(
+            drain(&queue),
+            vec![vec![0x90, 60, 100], vec![0x90, 61, 100], vec![0xB0, 7, 20]]
+        );
+    }
+
+    #[test]
+    fn newer_value_in_the_ring_replaces_the_held_one() {
+        let queue = EventQueue::new(2, Overflow::Coalesce);
+        queue.push(1, &[0x90, 60, 100]);
+        queue.push(2, &[0x90, 61, 100]);
+        queue.push(3, &[0xB0, 7, 10]);
+        assert!(queue.pop().is_some());
+        queue.push(4, &[0xB0, 7, 20]);
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(drain(&queue), vec![vec![0x90, 61, 100], vec![0xB0, 7, 20]]);
+    }
+
+    #[test]
+    fn sysex_is_counted_as_dropped() {
+        let queue = EventQueue::new(2, Overflow::DropOldest);
+        queue.push(0, &[0xF0, 1, 2, 3, 0xF7]);
+        assert_eq!(queue.dropped(), 1);
+        assert!(queue.pop().is_none());
+    }
+}
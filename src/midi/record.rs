@@ -0,0 +1,106 @@
+use midly::live::LiveEvent;
+use midly::num::{u15, u24, u28};
+use midly::{Arena, Format, Header, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use std::path::Path;
+
+use super::error::Error;
+use super::event::MidiEvent;
+
+// 120bpm at 960 ticks per beat, roughly half a millisecond per tick.
+pub const TICKS_PER_BEAT: u16 = 960;
+pub const MICROS_PER_BEAT: u32 = 500_000;
+
+// Collects events while recording and writes them out as a single track
+// Standard MIDI File. Delta times come from the midir timestamps.
+#[derive(Default)]
+pub struct Recorder {
+    recording: bool,
+    start_us: Option<u64>,
+    events: Vec<(u64, Vec<u8>)>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder::default()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    // Starts a new take, dropping anything recorded before.
+    pub fn start(&mut self) {
+        self.recording = true;
+        self.start_us = None;
+        self.events.clear();
+    }
+
+    pub fn stop(&mut self) {
+        self.recording = false;
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn record(&mut self, event: &MidiEvent) {
+        if !self.recording {
+            return;
+        }
+        let start = *self.start_us.get_or_insert(event.timestamp_us);
+        let mut bytes = vec![0u8; event.message.bytes_size()];
+        if event.message.copy_to_slice(&mut bytes).is_ok() {
+            self.events
+                .push((event.timestamp_us.saturating_sub(start), bytes));
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let arena = Arena::new();
+        let mut track = vec![TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(MICROS_PER_BEAT))),
+        }];
+
+        let mut last_tick = 0;
+        for (micros, bytes) in &self.events {
+            let live = match LiveEvent::parse(bytes) {
+                Ok(live) => live,
+                Err(_) => continue,
+            };
+            // Work from absolute ticks so rounding doesn't drift over a long take.
+            let tick = micros_to_ticks(*micros);
+            track.push(TrackEvent {
+                delta: u28::new(tick.saturating_sub(last_tick)),
+                kind: live.as_track_event(&arena),
+            });
+            // Sources with different timestamp origins can step back in
+            // time. Those events are written at the last tick instead of
+            // pushing everything after them late.
+            last_tick = last_tick.max(tick);
+        }
+        track.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+
+        let mut smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(u15::new(TICKS_PER_BEAT)),
+        ));
+        smf.tracks.push(track);
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        smf.save(path).map_err(Error::from)
+    }
+}
+
+pub(crate) fn micros_to_ticks(micros: u64) -> u32 {
+    (micros as f64 * TICKS_PER_BEAT as f64 / MICROS_PER_BEAT as f64).round() as u32
+}
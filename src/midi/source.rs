@@ -0,0 +1,82 @@
+use midir::MidiInputConnection;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+use wmidi::{Channel, ControlFunction, MidiMessage, Note, U7};
+
+use super::config::MidiConfig;
+use super::error::Error;
+use super::event::{Event, MidiEvent};
+
+// Anything that can feed events into a sketch. The handle keeps the source
+// running and should be stored on the model.
+pub trait MidiSource {
+    type Handle;
+
+    fn start(self, tx: Sender<Event>) -> Result<Self::Handle, Error>;
+}
+
+impl MidiSource for MidiConfig {
+    type Handle = MidiInputConnection<()>;
+
+    fn start(self, tx: Sender<Event>) -> Result<Self::Handle, Error> {
+        self.connect(tx)
+    }
+}
+
+// Sends a scripted sequence as soon as it is started, for tests and for
+// running sketches without hardware.
+#[derive(Clone, Debug, Default)]
+pub struct MockSource {
+    events: Vec<MidiEvent>,
+    now_us: u64,
+}
+
+pub const MOCK_PORT: &str = "mock";
+
+impl MockSource {
+    pub fn new() -> Self {
+        MockSource::default()
+    }
+
+    pub fn message(mut self, message: MidiMessage<'static>) -> Self {
+        self.events.push(MidiEvent {
+            timestamp_us: self.now_us,
+            port: Arc::from(MOCK_PORT),
+            message,
+        });
+        self
+    }
+
+    pub fn control_change(self, channel: Channel, control: ControlFunction, value: U7) -> Self {
+        self.message(MidiMessage::ControlChange(channel, control, value))
+    }
+
+    pub fn note_on(self, channel: Channel, note: Note, velocity: U7) -> Self {
+        self.message(MidiMessage::NoteOn(channel, note, velocity))
+    }
+
+    pub fn note_off(self, channel: Channel, note: Note, velocity: U7) -> Self {
+        self.message(MidiMessage::NoteOff(channel, note, velocity))
+    }
+
+    // Moves the timestamp of the following messages forward.
+    pub fn wait(mut self, duration: Duration) -> Self {
+        self.now_us += duration.as_micros() as u64;
+        self
+    }
+}
+
+impl MidiSource for MockSource {
+    type Handle = ();
+
+    fn start(self, tx: Sender<Event>) -> Result<Self::Handle, Error> {
+        for event in self.events {
+            // Nobody listening is not an error for a mock.
+            if tx.send(Event::Midi(event)).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
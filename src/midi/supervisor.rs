@@ -0,0 +1,98 @@
+use midir::{MidiInput, MidiInputConnection, MidiInputPort};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use super::config::MidiConfig;
+use super::event::Event;
+
+// Keeps a connection alive across unplugging and replugging the device.
+// The port list is polled on a background thread, which stops when the
+// `Supervisor` is dropped or the event receiver goes away.
+//
+// A device that is replugged between two polls keeps its name, so the port
+// itself is looked for: the backend gives it a new identity when it comes
+// back, and the dead connection is reopened.
+pub struct Supervisor {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Supervisor {
+    pub fn start(config: MidiConfig, tx: Sender<Event>) -> Supervisor {
+        let (stop_tx, stop_rx) = channel();
+        let handle = thread::spawn(move || {
+            let mut current: Option<(MidiInputConnection<()>, Arc<str>)> = None;
+            // The port `current` was opened on.
+            let mut opened: Option<MidiInputPort> = None;
+            let mut watcher: Option<MidiInput> = None;
+            loop {
+                if watcher.is_none() {
+                    watcher = MidiInput::new("watching ports").ok();
+                }
+                let ports = watcher.as_ref().and_then(list);
+
+                // Our own virtual port never shows up in the input list.
+                let lost = match (&current, &ports) {
+                    _ if config.is_virtual() => false,
+                    (Some(_), Some((ports, _))) => {
+                        opened.as_ref().is_none_or(|port| !ports.contains(port))
+                    }
+                    _ => false,
+                };
+                if lost {
+                    opened = None;
+                    if let Some((connection, port)) = current.take() {
+                        connection.close();
+                        if tx.send(Event::Disconnected(port)).is_err() {
+                            return;
+                        }
+                    }
+                }
+
+                let found = ports.as_ref().and_then(|(ports, names)| {
+                    let i = config.find_port(names)?;
+                    Some(ports[i].clone())
+                });
+                let available = config.is_virtual() || found.is_some();
+                if current.is_none() && available {
+                    if let Ok((connection, _, port)) = config.open(tx.clone()) {
+                        if tx.send(Event::Connected(port.clone())).is_err() {
+                            return;
+                        }
+                        current = Some((connection, port));
+                        opened = found;
+                    }
+                }
+
+                match stop_rx.recv_timeout(config.poll_interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
+            }
+        });
+
+        Supervisor {
+            stop: Some(stop_tx),
+            handle: Some(handle),
+        }
+    }
+}
+
+fn list(midi_in: &MidiInput) -> Option<(Vec<MidiInputPort>, Vec<String>)> {
+    let ports = midi_in.ports();
+    let names = ports
+        .iter()
+        .map(|p| midi_in.port_name(p).ok())
+        .collect::<Option<Vec<String>>>()?;
+    Some((ports, names))
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use wmidi::{Channel, ControlFunction, U7};
+
+pub const BANKS: u8 = 4;
+pub const ROWS: u8 = 4;
+pub const COLS: u8 = 4;
+pub const ENCODERS_PER_BANK: u8 = ROWS * COLS;
+pub const ENCODERS: u8 = BANKS * ENCODERS_PER_BANK;
+
+// Factory settings: every encoder turns on channel 1, using CC 0-63 in bank
+// order, then row, then column.
+pub const ENCODER_CHANNEL: Channel = Channel::Ch1;
+
+// Banks, rows and columns all count from 0 like the names in `constants`, so
+// the device's "bank 2" is `bank: 1`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Encoder {
+    pub bank: u8,
+    pub row: u8,
+    pub col: u8,
+}
+
+impl Encoder {
+    pub const fn new(bank: u8, row: u8, col: u8) -> Encoder {
+        assert!(bank < BANKS && row < ROWS && col < COLS);
+        Encoder { bank, row, col }
+    }
+
+    pub fn try_new(bank: u8, row: u8, col: u8) -> Option<Encoder> {
+        (bank < BANKS && row < ROWS && col < COLS).then_some(Encoder { bank, row, col })
+    }
+
+    pub fn from_index(index: u8) -> Option<Encoder> {
+        (index < ENCODERS).then_some(Encoder {
+            bank: index / ENCODERS_PER_BANK,
+            row: (index % ENCODERS_PER_BANK) / COLS,
+            col: index % COLS,
+        })
+    }
+
+    pub fn index(self) -> u8 {
+        self.bank * ENCODERS_PER_BANK + self.row * COLS + self.col
+    }
+
+    pub fn control(self) -> ControlFunction {
+        ControlFunction(U7::from_u8_lossy(self.index()))
+    }
+
+    pub fn to_cc(self) -> (Channel, ControlFunction) {
+        (ENCODER_CHANNEL, self.control())
+    }
+
+    // Only the CC number says which encoder it is. Different message kinds
+    // arrive on different channels, so `from_cc` checks for the turn channel.
+    pub fn from_control(control: ControlFunction) -> Option<Encoder> {
+        Encoder::from_index(u8::from(control))
+    }
+
+    pub fn from_cc(channel: Channel, control: ControlFunction) -> Option<Encoder> {
+        if channel != ENCODER_CHANNEL {
+            return None;
+        }
+        Encoder::from_control(control)
+    }
+
+    pub fn all() -> impl Iterator<Item = Encoder> {
+        (0..ENCODERS).filter_map(Encoder::from_index)
+    }
+
+    pub fn bank_encoders(bank: u8) -> impl Iterator<Item = Encoder> {
+        Encoder::all().filter(move |e| e.bank == bank)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::twister::constants;
+
+    #[test]
+    fn index_round_trips() {
+        assert_eq!(Encoder::all().count(), ENCODERS as usize);
+        for (i, encoder) in Encoder::all().enumerate() {
+            assert_eq!(encoder.index(), i as u8);
+            assert_eq!(Encoder::from_index(encoder.index()), Some(encoder));
+            let (channel, control) = encoder.to_cc();
+            assert_eq!(Encoder::from_cc(channel, control), Some(encoder));
+        }
+        assert_eq!(Encoder::from_index(ENCODERS), None);
+    }
+
+    #[test]
+    fn layout_matches_the_constants() {
+        assert_eq!(Encoder::new(0, 1, 2).control(), constants::ONE_TWO);
+        assert_eq!(Encoder::from_index(16), Some(Encoder::new(1, 0, 0)));
+        assert_eq!(Encoder::new(3, 3, 3).index(), 63);
+    }
+
+    #[test]
+    fn other_channels_and_out_of_range() {
+        let control = Encoder::new(0, 0, 0).control();
+        assert_eq!(Encoder::from_cc(Channel::Ch2, control), None);
+        assert_eq!(Encoder::try_new(4, 0, 0), None);
+        assert_eq!(Encoder::try_new(0, 0, 4), None);
+        assert_eq!(Encoder::bank_encoders(2).count(), ENCODERS_PER_BANK as usize);
+    }
+}
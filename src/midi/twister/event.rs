@@ -0,0 +1,184 @@
+use wmidi::{Channel, ControlFunction, MidiMessage, U7};
+
+use super::encoder::{Encoder, BANKS, ENCODER_CHANNEL};
+
+// Factory settings for the messages that aren't encoder turns.
+pub const SWITCH_CHANNEL: Channel = Channel::Ch2;
+pub const SYSTEM_CHANNEL: Channel = Channel::Ch4;
+// CC 0-3 on the system channel select a bank, CC 8-31 are the six side
+// buttons of each bank in turn.
+pub const FIRST_SIDE_BUTTON_CC: u8 = 8;
+pub const SIDE_BUTTONS_PER_BANK: u8 = 6;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SideButton {
+    LeftTop,
+    LeftMiddle,
+    LeftBottom,
+    RightTop,
+    RightMiddle,
+    RightBottom,
+}
+
+impl SideButton {
+    pub const ALL: [SideButton; 6] = [
+        SideButton::LeftTop,
+        SideButton::LeftMiddle,
+        SideButton::LeftBottom,
+        SideButton::RightTop,
+        SideButton::RightMiddle,
+        SideButton::RightBottom,
+    ];
+
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+
+    pub fn control(self, bank: u8) -> ControlFunction {
+        ControlFunction(U7::from_u8_lossy(
+            FIRST_SIDE_BUTTON_CC + bank * SIDE_BUTTONS_PER_BANK + self.index(),
+        ))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TwisterEvent {
+    Turned {
+        encoder: Encoder,
+        value: U7,
+    },
+    EncoderPressed(Encoder),
+    EncoderReleased(Encoder),
+    SideButton {
+        bank: u8,
+        button: SideButton,
+        pressed: bool,
+    },
+    BankChanged(u8),
+}
+
+// Encoder turns are the only Twister messages that are positions rather than
+// presses, so the only ones `midi::coalesce` should merge.
+pub fn is_turn(channel: Channel, _control: ControlFunction) -> bool {
+    channel == ENCODER_CHANNEL
+}
+
+pub fn decode(message: &MidiMessage) -> Option<TwisterEvent> {
+    let (channel, control, value) = match message {
+        MidiMessage::ControlChange(channel, control, value) => (*channel, *control, *value),
+        _ => return None,
+    };
+    let pressed = u8::from(value) > 0;
+
+    match channel {
+        ENCODER_CHANNEL => {
+            Encoder::from_control(control).map(|encoder| TwisterEvent::Turned { encoder, value })
+        }
+        SWITCH_CHANNEL => Encoder::from_control(control).map(|encoder| {
+            if pressed {
+                TwisterEvent::EncoderPressed(encoder)
+            } else {
+                TwisterEvent::EncoderReleased(encoder)
+            }
+        }),
+        SYSTEM_CHANNEL => {
+            let cc = u8::from(control);
+            if cc < BANKS {
+                // The Twister also sends 0 for the bank it just left.
+                return pressed.then_some(TwisterEvent::BankChanged(cc));
+            }
+            let side = cc.checked_sub(FIRST_SIDE_BUTTON_CC)?;
+            let bank = side / SIDE_BUTTONS_PER_BANK;
+            let button = *SideButton::ALL.get((side % SIDE_BUTTONS_PER_BANK) as usize)?;
+            (bank < BANKS).then_some(TwisterEvent::SideButton {
+                bank,
+                button,
+                pressed,
+            })
+        }
+        _ => None,
+    }
+}
+
+// The message the Twister sends for `event`, so it can be faked without the
+// device. Turns carry whatever value the encoder is set up to send.
+pub fn encode(event: &TwisterEvent) -> MidiMessage<'static> {
+    let on_off = |on: bool| if on { U7::MAX } else { U7::MIN };
+    let (channel, control, value) = match *event {
+        TwisterEvent::Turned { encoder, value } => (ENCODER_CHANNEL, encoder.control(), value),
+        TwisterEvent::EncoderPressed(encoder) => (SWITCH_CHANNEL, encoder.control(), U7::MAX),
+        TwisterEvent::EncoderReleased(encoder) => (SWITCH_CHANNEL, encoder.control(), U7::MIN),
+        TwisterEvent::SideButton {
+            bank,
+            button,
+            pressed,
+        } => (SYSTEM_CHANNEL, button.control(bank), on_off(pressed)),
+        TwisterEvent::BankChanged(bank) => (
+            SYSTEM_CHANNEL,
+            ControlFunction(U7::from_u8_lossy(bank)),
+            U7::MAX,
+        ),
+    };
+    MidiMessage::ControlChange(channel, control, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(event: TwisterEvent) {
+        assert_eq!(decode(&encode(&event)), Some(event), "{:?}", event);
+    }
+
+    #[test]
+    fn encoder_events_round_trip() {
+        for encoder in Encoder::all() {
+            round_trip(TwisterEvent::Turned {
+                encoder,
+                value: U7::from_u8_lossy(encoder.index() * 2),
+            });
+            round_trip(TwisterEvent::EncoderPressed(encoder));
+            round_trip(TwisterEvent::EncoderReleased(encoder));
+        }
+    }
+
+    #[test]
+    fn side_buttons_round_trip() {
+        for bank in 0..BANKS {
+            for button in SideButton::ALL {
+                for pressed in [true, false] {
+                    round_trip(TwisterEvent::SideButton {
+                        bank,
+                        button,
+                        pressed,
+                    });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bank_changes() {
+        for bank in 0..BANKS {
+            round_trip(TwisterEvent::BankChanged(bank));
+        }
+        // Sent for the bank being left, not a change of its own.
+        let left = MidiMessage::ControlChange(
+            SYSTEM_CHANNEL,
+            ControlFunction(U7::from_u8_lossy(1)),
+            U7::MIN,
+        );
+        assert_eq!(decode(&left), None);
+    }
+
+    #[test]
+    fn unknown_messages() {
+        let cc = |channel, cc| {
+            MidiMessage::ControlChange(channel, ControlFunction(U7::from_u8_lossy(cc)), U7::MAX)
+        };
+        assert_eq!(decode(&cc(ENCODER_CHANNEL, 64)), None);
+        assert_eq!(decode(&cc(SYSTEM_CHANNEL, 4)), None);
+        assert_eq!(decode(&cc(SYSTEM_CHANNEL, 32)), None);
+        assert_eq!(decode(&cc(Channel::Ch5, 0)), None);
+    }
+}
@@ -0,0 +1,85 @@
+use wmidi::{Channel, U7};
+
+use super::encoder::Encoder;
+use crate::midi::error::Error;
+use crate::midi::output::Output;
+
+// The Twister takes the ring colour on the switch channel and animations on
+// the one after it, both addressed by the encoder's CC number.
+pub const COLOR_CHANNEL: Channel = Channel::Ch2;
+pub const ANIMATION_CHANNEL: Channel = Channel::Ch3;
+
+// Points on the Twister's hue wheel, which runs from blue at 1 round to
+// magenta at 126. 0 and 127 switch back to the configured off/on colours.
+pub const BLUE: U7 = U7::from_u8_lossy(1);
+pub const CYAN: U7 = U7::from_u8_lossy(25);
+pub const GREEN: U7 = U7::from_u8_lossy(50);
+pub const YELLOW: U7 = U7::from_u8_lossy(64);
+pub const RED: U7 = U7::from_u8_lossy(85);
+pub const MAGENTA: U7 = U7::from_u8_lossy(110);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Animation {
+    None,
+    // Rates run from 0 (slowest) to 7.
+    Strobe(u8),
+    Pulse(u8),
+    IndicatorNone,
+    IndicatorStrobe(u8),
+    IndicatorPulse(u8),
+    Rainbow,
+}
+
+impl Animation {
+    pub fn value(self) -> U7 {
+        let rate = |r: u8| r.min(7);
+        U7::from_u8_lossy(match self {
+            Animation::None => 0,
+            Animation::Strobe(r) => 1 + rate(r),
+            Animation::Pulse(r) => 9 + rate(r),
+            Animation::IndicatorNone => 48,
+            Animation::IndicatorStrobe(r) => 49 + rate(r),
+            Animation::IndicatorPulse(r) => 57 + rate(r),
+            Animation::Rainbow => 127,
+        })
+    }
+}
+
+pub fn set_color(output: &mut Output, encoder: Encoder, hue: U7) -> Result<(), Error> {
+    output.control_change(COLOR_CHANNEL, encoder.control(), hue)
+}
+
+// Brightness of the RGB ring, 0..1.
+pub fn set_brightness(output: &mut Output, encoder: Encoder, brightness: f32) -> Result<(), Error> {
+    output.control_change(
+        ANIMATION_CHANNEL,
+        encoder.control(),
+        scaled(17, 47, brightness),
+    )
+}
+
+// Brightness of the indicator ring that shows the value, 0..1.
+pub fn set_indicator_brightness(
+    output: &mut Output,
+    encoder: Encoder,
+    brightness: f32,
+) -> Result<(), Error> {
+    output.control_change(
+        ANIMATION_CHANNEL,
+        encoder.control(),
+        scaled(65, 95, brightness),
+    )
+}
+
+pub fn set_animation(
+    output: &mut Output,
+    encoder: Encoder,
+    animation: Animation,
+) -> Result<(), Error> {
+    output.control_change(ANIMATION_CHANNEL, encoder.control(), animation.value())
+}
+
+fn scaled(min: u8, max: u8, amount: f32) -> U7 {
+    let range = (max - min) as f32;
+    U7::from_u8_lossy(min + (amount.clamp(0.0, 1.0) * range).round() as u8)
+}
@@ -1 +1,14 @@
-pub mod constants;
\ No newline at end of file
+pub mod constants;
+pub mod encoder;
+pub mod event;
+pub mod lights;
+pub mod relative;
+pub mod simulator;
+pub mod state;
+
+pub use encoder::Encoder;
+pub use event::{decode, encode, is_turn, SideButton, TwisterEvent};
+pub use lights::{set_animation, set_brightness, set_color, set_indicator_brightness, Animation};
+pub use relative::{Accumulator, Encoding};
+pub use simulator::{Simulator, VirtualTwister};
+pub use state::{EncoderState, TwisterState};
@@ -0,0 +1,153 @@
+use wmidi::U7;
+
+use super::encoder::{Encoder, ENCODERS};
+use super::event::TwisterEvent;
+
+// What the encoders send when turned, set per encoder in the MIDI Fighter
+// Utility.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    // 0-127, the factory setting.
+    Absolute,
+    // "ENC 3FH/41H": 64 plus the number of steps turned, so 63 is one step
+    // down and 65 one step up.
+    Relative,
+}
+
+// Turns encoder movement into a value per encoder that can keep going past
+// the ends of the 0-127 range. Absolute encoders only count the change since
+// their last message, so values don't jump when switching sketches.
+#[derive(Clone, Debug)]
+pub struct Accumulator {
+    encoding: Encoding,
+    sensitivity: f32,
+    range: Option<(f32, f32)>,
+    values: [f32; ENCODERS as usize],
+    last_absolute: [Option<u8>; ENCODERS as usize],
+}
+
+impl Accumulator {
+    pub fn new(encoding: Encoding) -> Self {
+        Accumulator {
+            encoding,
+            sensitivity: 1.0 / 127.0,
+            range: None,
+            values: [0.0; ENCODERS as usize],
+            last_absolute: [None; ENCODERS as usize],
+        }
+    }
+
+    // How far one step moves the value.
+    pub fn sensitivity(mut self, per_step: f32) -> Self {
+        self.sensitivity = per_step;
+        self
+    }
+
+    pub fn clamp(mut self, min: f32, max: f32) -> Self {
+        self.range = Some((min, max));
+        self
+    }
+
+    pub fn unbounded(mut self) -> Self {
+        self.range = None;
+        self
+    }
+
+    pub fn get(&self, encoder: Encoder) -> f32 {
+        self.values[encoder.index() as usize]
+    }
+
+    pub fn set(&mut self, encoder: Encoder, value: f32) {
+        self.values[encoder.index() as usize] = self.limit(value);
+    }
+
+    // Where an absolute encoder's ring is, e.g. after sending it a value, so
+    // its first turn counts from there instead of being dropped.
+    pub fn sync(&mut self, encoder: Encoder, ring: U7) {
+        self.last_absolute[encoder.index() as usize] = Some(u8::from(ring));
+    }
+
+    // Returns the encoder and its new value if the event moved one.
+    pub fn feed(&mut self, event: &TwisterEvent) -> Option<(Encoder, f32)> {
+        let (encoder, value) = match event {
+            TwisterEvent::Turned { encoder, value } => (*encoder, u8::from(*value)),
+            _ => return None,
+        };
+        let i = encoder.index() as usize;
+        let steps = match self.encoding {
+            Encoding::Relative => value as i32 - 64,
+            Encoding::Absolute => {
+                let last = self.last_absolute[i].replace(value);
+                last.map_or(0, |last| value as i32 - last as i32)
+            }
+        };
+        self.values[i] = self.limit(self.values[i] + steps as f32 * self.sensitivity);
+        Some((encoder, self.values[i]))
+    }
+
+    fn limit(&self, value: f32) -> f32 {
+        match self.range {
+            Some((min, max)) => value.clamp(min, max),
+            None => value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KNOB: Encoder = Encoder::new(0, 0, 0);
+
+    fn turn(accumulator: &mut Accumulator, value: u8) -> Option<f32> {
+        let event = TwisterEvent::Turned {
+            encoder: KNOB,
+            value: U7::from_u8_lossy(value),
+        };
+        accumulator.feed(&event).map(|(_, value)| value)
+    }
+
+    #[test]
+    fn relative_steps_from_64() {
+        let mut knobs = Accumulator::new(Encoding::Relative).sensitivity(1.0);
+        assert_eq!(turn(&mut knobs, 65), Some(1.0));
+        assert_eq!(turn(&mut knobs, 67), Some(4.0));
+        assert_eq!(turn(&mut knobs, 63), Some(3.0));
+        assert_eq!(turn(&mut knobs, 64), Some(3.0));
+    }
+
+    #[test]
+    fn absolute_counts_changes() {
+        let mut knobs = Accumulator::new(Encoding::Absolute).sensitivity(1.0);
+        // Nothing to count the first value from.
+        assert_eq!(turn(&mut knobs, 100), Some(0.0));
+        assert_eq!(turn(&mut knobs, 103), Some(3.0));
+        assert_eq!(turn(&mut knobs, 90), Some(-10.0));
+    }
+
+    #[test]
+    fn sync_counts_the_first_turn() {
+        let mut knobs = Accumulator::new(Encoding::Absolute).sensitivity(1.0);
+        knobs.sync(KNOB, U7::from_u8_lossy(6));
+        assert_eq!(turn(&mut knobs, 10), Some(4.0));
+    }
+
+    #[test]
+    fn clamp_and_unbounded() {
+        let mut knobs = Accumulator::new(Encoding::Relative)
+            .sensitivity(1.0)
+            .clamp(0.0, 2.0);
+        assert_eq!(turn(&mut knobs, 60), Some(0.0));
+        assert_eq!(turn(&mut knobs, 74), Some(2.0));
+
+        let mut knobs = knobs.unbounded();
+        assert_eq!(turn(&mut knobs, 54), Some(-8.0));
+    }
+
+    #[test]
+    fn other_events_are_ignored() {
+        let mut knobs = Accumulator::new(Encoding::Relative);
+        assert_eq!(knobs.feed(&TwisterEvent::EncoderPressed(KNOB)), None);
+        assert_eq!(knobs.get(KNOB), 0.0);
+    }
+}
@@ -0,0 +1,269 @@
+use nannou::prelude::Key;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wmidi::U7;
+
+use super::encoder::{Encoder, BANKS, COLS, ENCODERS, ROWS};
+use super::event::{encode, SideButton, TwisterEvent};
+use super::relative::Encoding;
+use super::state::TwisterState;
+use crate::midi::error::Error;
+use crate::midi::event::{Event, MidiEvent};
+use crate::midi::source::MidiSource;
+
+pub const SIMULATOR_PORT: &str = "Twister simulator";
+
+#[derive(Copy, Clone, Debug)]
+enum Action {
+    Turn(Encoder, i32),
+    Press(Encoder),
+    Release(Encoder),
+    SideButton(SideButton, bool),
+    Bank(u8),
+}
+
+// A software Twister that sends the same messages as the real one, driven by
+// a script and then by the keyboard, for working without the device.
+#[derive(Clone, Debug)]
+pub struct Simulator {
+    label: String,
+    encoding: Encoding,
+    step: i32,
+    values: [U7; ENCODERS as usize],
+    bank: u8,
+    script: Vec<(u64, Action)>,
+    now_us: u64,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Simulator {
+            label: SIMULATOR_PORT.to_owned(),
+            encoding: Encoding::Absolute,
+            step: 4,
+            values: [U7::MIN; ENCODERS as usize],
+            bank: 0,
+            script: Vec::new(),
+            now_us: 0,
+        }
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Simulator::default()
+    }
+
+    // Tags events with `label`, e.g. to match a label given to the hardware
+    // input.
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = label.to_owned();
+        self
+    }
+
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    // How many steps one key press turns an encoder.
+    pub fn step(mut self, steps: i32) -> Self {
+        self.step = steps;
+        self
+    }
+
+    // Starts from the values a sketch initialised its state with, so the
+    // first turn moves from there instead of jumping from 0.
+    pub fn state(mut self, state: &TwisterState) -> Self {
+        for encoder in Encoder::all() {
+            self.values[encoder.index() as usize] = state.encoder(encoder).value;
+        }
+        self.bank = state.bank();
+        self
+    }
+
+    // The script is sent as soon as the simulator is started.
+    pub fn turn(self, encoder: Encoder, steps: i32) -> Self {
+        self.then(Action::Turn(encoder, steps))
+    }
+
+    pub fn press(self, encoder: Encoder) -> Self {
+        self.then(Action::Press(encoder))
+    }
+
+    pub fn release(self, encoder: Encoder) -> Self {
+        self.then(Action::Release(encoder))
+    }
+
+    pub fn side_button(self, button: SideButton, pressed: bool) -> Self {
+        self.then(Action::SideButton(button, pressed))
+    }
+
+    pub fn bank(self, bank: u8) -> Self {
+        self.then(Action::Bank(bank))
+    }
+
+    // Moves the timestamp of the following script steps forward.
+    pub fn wait(mut self, duration: Duration) -> Self {
+        self.now_us += duration.as_micros() as u64;
+        self
+    }
+
+    fn then(mut self, action: Action) -> Self {
+        self.script.push((self.now_us, action));
+        self
+    }
+}
+
+impl MidiSource for Simulator {
+    type Handle = VirtualTwister;
+
+    fn start(self, tx: Sender<Event>) -> Result<Self::Handle, Error> {
+        let port: Arc<str> = Arc::from(self.label.as_str());
+        // Nobody listening is not an error for a simulator.
+        let _ = tx.send(Event::Connected(port.clone()));
+
+        let mut twister = VirtualTwister {
+            port,
+            tx,
+            encoding: self.encoding,
+            step: self.step,
+            values: self.values,
+            bank: self.bank,
+            selected: Encoder::new(self.bank, 0, 0),
+            started: Instant::now(),
+            offset_us: self.now_us,
+        };
+        for (timestamp_us, action) in self.script {
+            twister.apply(timestamp_us, action);
+        }
+        Ok(twister)
+    }
+}
+
+// A started simulator. Keyboard control:
+//   arrows   select an encoder in the current bank
+//   - / =    turn it down / up
+//   space    press it while held
+//   1 - 4    switch bank
+pub struct VirtualTwister {
+    port: Arc<str>,
+    tx: Sender<Event>,
+    encoding: Encoding,
+    step: i32,
+    values: [U7; ENCODERS as usize],
+    bank: u8,
+    selected: Encoder,
+    started: Instant,
+    // Live events are timed after the end of the script.
+    offset_us: u64,
+}
+
+impl VirtualTwister {
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+
+    pub fn selected(&self) -> Encoder {
+        self.selected
+    }
+
+    pub fn value(&self, encoder: Encoder) -> U7 {
+        self.values[encoder.index() as usize]
+    }
+
+    pub fn turn(&mut self, encoder: Encoder, steps: i32) {
+        self.act(Action::Turn(encoder, steps));
+    }
+
+    pub fn press(&mut self, encoder: Encoder) {
+        self.act(Action::Press(encoder));
+    }
+
+    pub fn release(&mut self, encoder: Encoder) {
+        self.act(Action::Release(encoder));
+    }
+
+    pub fn side_button(&mut self, button: SideButton, pressed: bool) {
+        self.act(Action::SideButton(button, pressed));
+    }
+
+    pub fn bank(&mut self, bank: u8) {
+        self.act(Action::Bank(bank));
+    }
+
+    // Returns whether the key was one of the simulator's.
+    pub fn key_pressed(&mut self, key: Key) -> bool {
+        let Encoder { bank, row, col } = self.selected;
+        match key {
+            Key::Left => self.select(row, col.saturating_sub(1)),
+            Key::Right => self.select(row, (col + 1).min(COLS - 1)),
+            Key::Up => self.select(row.saturating_sub(1), col),
+            Key::Down => self.select((row + 1).min(ROWS - 1), col),
+            Key::Minus | Key::NumpadSubtract => self.turn(self.selected, -self.step),
+            Key::Equals | Key::Plus | Key::NumpadAdd => self.turn(self.selected, self.step),
+            Key::Space => self.press(self.selected),
+            Key::Key1 if bank != 0 => self.bank(0),
+            Key::Key2 if bank != 1 => self.bank(1),
+            Key::Key3 if bank != 2 => self.bank(2),
+            Key::Key4 if bank != 3 => self.bank(3),
+            Key::Key1 | Key::Key2 | Key::Key3 | Key::Key4 => {}
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn key_released(&mut self, key: Key) -> bool {
+        match key {
+            Key::Space => self.release(self.selected),
+            _ => return false,
+        }
+        true
+    }
+
+    fn select(&mut self, row: u8, col: u8) {
+        self.selected = Encoder::new(self.bank, row, col);
+    }
+
+    fn act(&mut self, action: Action) {
+        let timestamp_us = self.offset_us + self.started.elapsed().as_micros() as u64;
+        self.apply(timestamp_us, action);
+    }
+
+    fn apply(&mut self, timestamp_us: u64, action: Action) {
+        let event = match action {
+            Action::Turn(encoder, steps) => {
+                let i = encoder.index() as usize;
+                let absolute = (u8::from(self.values[i]) as i32 + steps).clamp(0, 127);
+                self.values[i] = U7::from_u8_lossy(absolute as u8);
+                let value = match self.encoding {
+                    Encoding::Absolute => absolute,
+                    Encoding::Relative => (64 + steps).clamp(0, 127),
+                };
+                TwisterEvent::Turned {
+                    encoder,
+                    value: U7::from_u8_lossy(value as u8),
+                }
+            }
+            Action::Press(encoder) => TwisterEvent::EncoderPressed(encoder),
+            Action::Release(encoder) => TwisterEvent::EncoderReleased(encoder),
+            Action::SideButton(button, pressed) => TwisterEvent::SideButton {
+                bank: self.bank,
+                button,
+                pressed,
+            },
+            Action::Bank(bank) => {
+                let bank = bank.min(BANKS - 1);
+                self.bank = bank;
+                self.selected.bank = bank;
+                TwisterEvent::BankChanged(bank)
+            }
+        };
+        let _ = self.tx.send(Event::Midi(MidiEvent {
+            timestamp_us,
+            port: self.port.clone(),
+            message: encode(&event),
+        }));
+    }
+}
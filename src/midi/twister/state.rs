@@ -0,0 +1,100 @@
+use std::time::Instant;
+use wmidi::{MidiMessage, U7};
+
+use super::encoder::{Encoder, ENCODERS};
+use super::event::{decode, TwisterEvent};
+use crate::util;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct EncoderState {
+    pub value: U7,
+    pub pressed: bool,
+    // When the encoder was last turned or pressed, `None` if it hasn't been.
+    pub changed: Option<Instant>,
+}
+
+// Latest value of every encoder on every bank. Lookups name the bank through
+// the `Encoder`, so switching banks on the Twister doesn't change what a
+// sketch reads.
+#[derive(Clone, Debug)]
+pub struct TwisterState {
+    encoders: [EncoderState; ENCODERS as usize],
+    bank: u8,
+}
+
+impl Default for TwisterState {
+    fn default() -> Self {
+        TwisterState {
+            encoders: [EncoderState::default(); ENCODERS as usize],
+            bank: 0,
+        }
+    }
+}
+
+impl TwisterState {
+    pub fn new() -> Self {
+        TwisterState::default()
+    }
+
+    // Updates the state and hands back the decoded event, for sketches that
+    // also want to react to presses as they happen.
+    pub fn feed(&mut self, message: &MidiMessage) -> Option<TwisterEvent> {
+        let event = decode(message)?;
+        let now = Some(Instant::now());
+        match event {
+            TwisterEvent::Turned { encoder, value } => {
+                let state = self.encoder_mut(encoder);
+                state.value = value;
+                state.changed = now;
+            }
+            TwisterEvent::EncoderPressed(encoder) | TwisterEvent::EncoderReleased(encoder) => {
+                let state = self.encoder_mut(encoder);
+                state.pressed = matches!(event, TwisterEvent::EncoderPressed(_));
+                state.changed = now;
+            }
+            TwisterEvent::BankChanged(bank) => self.bank = bank,
+            TwisterEvent::SideButton { .. } => {}
+        }
+        Some(event)
+    }
+
+    pub fn bank(&self) -> u8 {
+        self.bank
+    }
+
+    // Sets a starting value, e.g. so a parameter's default isn't read back
+    // as 0 before the knob is touched.
+    pub fn set(&mut self, encoder: Encoder, value: U7) {
+        self.encoder_mut(encoder).value = value;
+    }
+
+    pub fn encoder(&self, encoder: Encoder) -> &EncoderState {
+        &self.encoders[encoder.index() as usize]
+    }
+
+    fn encoder_mut(&mut self, encoder: Encoder) -> &mut EncoderState {
+        &mut self.encoders[encoder.index() as usize]
+    }
+
+    pub fn raw(&self, encoder: Encoder) -> U7 {
+        self.encoder(encoder).value
+    }
+
+    // 0..1
+    pub fn get(&self, encoder: Encoder) -> f32 {
+        util::unipolar(self.raw(encoder))
+    }
+
+    // -1..1, 0 at the centre of the ring.
+    pub fn bipolar(&self, encoder: Encoder) -> f32 {
+        util::bipolar(self.raw(encoder), 0.0)
+    }
+
+    pub fn pressed(&self, encoder: Encoder) -> bool {
+        self.encoder(encoder).pressed
+    }
+
+    pub fn changed(&self, encoder: Encoder) -> Option<Instant> {
+        self.encoder(encoder).changed
+    }
+}
@@ -1,4 +1,90 @@
+use wmidi::{U14, U7};
 
 pub fn speed_func(val: f32) -> f32 {
   return 2.0f32.powf((val / 32.0) - 2.0)
 }
+
+pub fn inverse_speed_func(speed: f32) -> f32 {
+  (speed.log2() + 2.0) * 32.0
+}
+
+// 0..1
+pub fn unipolar(value: U7) -> f32 {
+  u8::from(value) as f32 / 127.0
+}
+
+// 0..1 for 14-bit values, see `midi::HighResDecoder`.
+pub fn unipolar14(value: U14) -> f32 {
+  u16::from(value) as f32 / 16383.0
+}
+
+// 1..0, for knobs that should read backwards.
+pub fn inverted(value: U7) -> f32 {
+  1.0 - unipolar(value)
+}
+
+// -1..1 around a centre of 64, reaching both ends even though there are more
+// values below the centre than above it. Anything within `deadzone` (as a
+// fraction of each half) of the centre reads as exactly 0.
+pub fn bipolar(value: U7, deadzone: f32) -> f32 {
+  let v = u8::from(value) as f32 - 64.0;
+  let centred = if v < 0.0 { v / 64.0 } else { v / 63.0 };
+  if centred.abs() <= deadzone {
+    return 0.0;
+  }
+  centred.signum() * (centred.abs() - deadzone) / (1.0 - deadzone)
+}
+
+// Splits the range into `steps` equal parts and returns which one `value` is
+// in, 0..steps.
+pub fn stepped(value: U7, steps: usize) -> usize {
+  u8::from(value) as usize * steps / 128
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn u7(value: u8) -> U7 {
+    U7::from_u8_lossy(value)
+  }
+
+  #[test]
+  fn bipolar_reaches_both_ends() {
+    assert_eq!(bipolar(u7(0), 0.0), -1.0);
+    assert_eq!(bipolar(u7(64), 0.0), 0.0);
+    assert_eq!(bipolar(u7(127), 0.0), 1.0);
+    // The lower half has one more value than the upper.
+    assert_eq!(bipolar(u7(32), 0.0), -0.5);
+    assert_eq!(bipolar(u7(63), 0.0), -1.0 / 64.0);
+    assert_eq!(bipolar(u7(65), 0.0), 1.0 / 63.0);
+  }
+
+  #[test]
+  fn bipolar_deadzone() {
+    assert_eq!(bipolar(u7(63), 0.05), 0.0);
+    assert_eq!(bipolar(u7(67), 0.05), 0.0);
+    assert!(bipolar(u7(68), 0.05) > 0.0);
+    assert!(bipolar(u7(60), 0.05) < 0.0);
+    assert_eq!(bipolar(u7(0), 0.05), -1.0);
+    assert_eq!(bipolar(u7(127), 0.05), 1.0);
+  }
+
+  #[test]
+  fn stepped_splits_evenly() {
+    assert_eq!(stepped(u7(0), 4), 0);
+    assert_eq!(stepped(u7(31), 4), 0);
+    assert_eq!(stepped(u7(32), 4), 1);
+    assert_eq!(stepped(u7(127), 4), 3);
+    assert_eq!(stepped(u7(127), 21), 20);
+    assert_eq!(stepped(u7(127), 1), 0);
+  }
+
+  #[test]
+  fn unipolar_and_inverted() {
+    assert_eq!(unipolar(u7(0)), 0.0);
+    assert_eq!(unipolar(u7(127)), 1.0);
+    assert_eq!(inverted(u7(0)), 1.0);
+    assert_eq!(inverted(u7(127)), 0.0);
+  }
+}